@@ -34,10 +34,47 @@ Lines starting with `###` will not be understood as doc comment.
 There can be several `## ` comments, but they must always be followed by a
 feature name or an optional dependency.
 There should not be `#! ` comments between the comment and the feature they document.
+Other comments, such as `# note`, are ignored, even in the middle of a block of `## ` comments:
+the `## ` lines before and after them are part of the same description.
 
 `#! ` comments are not associated with a particular feature, and will be printed
 in where they occur. Use them to group features, for example.
 
+### Tags
+
+The `## ` comments may contain lines with tags of the form `## @name: value`. These lines are not
+part of the description of the feature but provide extra information about it.
+
+ - `@stability`: `stable` (the default), `experimental`, or `deprecated`. Experimental and
+   deprecated features are marked as such in the output.
+ - `@alternative-to`: a comma separated list of features that can be used instead of this one,
+   such as another backend. They are listed after the description as "Alternative to: ...".
+ - `@icon`: an emoji or other text shown before the name of the feature. A feature can only have
+   one icon.
+ - `@requires`, `@platform`, `@msrv`: what the feature needs, such as `nightly`, `unix` or `1.70`
+   (the minimum Rust version). `requirements_summary = true` lists them all at the top.
+ - `@enables-item`: the path of an item that the feature enables, such as `crate::foo::Bar`.
+   It is listed after the description as ``Enables: [`crate::foo::Bar`]``, an intra-doc link that
+   rustdoc resolves to the item. This tag can be repeated, or contain a comma separated list.
+ - `@since`: the version of the crate that introduced the feature, such as `1.2`. It is only part
+   of the `"html"` and `"dl"` output, with `data_attributes = true`.
+ - `@default-on`: a comma separated list of platforms, such as `windows`, where the feature is
+   effectively required. Cargo has no default features per target, so this is only documentation:
+   it is listed after the description as "Enabled by default on: ...".
+ - `@non-additive`: the feature changes the behavior of the crate instead of only adding to it,
+   so enabling it from another crate can break other users of the crate. A warning is shown
+   after the description.
+
+```toml
+[features]
+## Enable the new renderer
+## @stability: experimental
+new-renderer = []
+## Use the software backend
+## @alternative-to: backend-gpu
+backend-software = []
+```
+
 ## Examples:
 
 */
@@ -103,6 +140,8 @@ the key **`feature_label=`** to a given format string. This format string must b
 a [string literal](https://doc.rust-lang.org/reference/tokens.html#string-literals) or
 a [raw string literal](https://doc.rust-lang.org/reference/tokens.html#raw-string-literals).
 Every occurrence of `{feature}` inside the format string will be substituted with the name of the feature.
+If the format string puts the feature name in a code span (`` `{feature}` ``) and the name
+contains backticks, more backticks are used to delimit the code span so that it is not broken.
 
 For instance, to emulate the HTML formatting used by `rustdoc` one can use the following:
 
@@ -116,6 +155,341 @@ The default formatting is equivalent to:
 #![doc = document_features::document_features!(feature_label = "**`{feature}`**")]
 ```
 
+*/
+#![doc = self_test!(/**
+[features]
+## Enable the foo functions
+foo = []
+## Enable the bar module
+bar = []
+*/, feature_label = "<code>{feature}</code>"
+=>
+    /**
+* <code>foo</code> —  Enable the foo functions
+* <code>bar</code> —  Enable the bar module
+*/
+)]
+/*!
+
+For more control, the key **`template=`** replaces the whole line of each feature. In this
+string, `{feature}` is replaced by the name of the feature, `{default}` by the default marker
+(or nothing if the feature is not enabled by default) and `{doc}` by the description.
+The template must contain `{feature}`. The icon, stability and other annotations of the
+feature are not added to the line in that case.
+
+```rust
+#![doc = document_features::document_features!(template = "- `{feature}`{default}: {doc}")]
+```
+
+The key **`line_template=`** is similar, but `{label}` is replaced by the name of the feature
+formatted with `feature_label` (or `default_feature_label`), and `{name}` by the bare name of the
+feature. The line template must contain `{label}` or `{name}` and takes precedence over
+`template`, `bullet` and `separator`.
+
+```rust
+#![doc = document_features::document_features!(line_template = "- {label}{default} — {doc}")]
+```
+
+Several arguments can be given to the macro, separated by commas.
+
+### Output format
+
+The key **`format=`** selects the kind of output that is generated:
+
+ - `"markdown"` (the default): a markdown list of the features and their documentation.
+ - `"badges"`: a single line of [shields.io](https://shields.io) badges, one per feature, which is
+   convenient to embed in a README. The comments are not part of this output.
+   The base URL of the badges can be changed with **`badge_url=`** (default: `"https://img.shields.io/badge"`),
+   and their colors with **`badge_color=`** (default: `"blue"`) and **`badge_default_color=`**
+   (default: `"brightgreen"`, used for the features enabled by default).
+
+```rust
+#![doc = document_features::document_features!(format = "badges", badge_color = "orange")]
+```
+
+ - `"mermaid"`: a [Mermaid](https://mermaid.js.org) graph in a fenced code block, showing which
+   features are enabled by each feature of the `[features]` table, documented or not.
+   Optional dependencies (`dep:foo`) and features of dependencies (`foo/bar`) are drawn with a
+   dashed border.
+ - `"tiers"`: a table comparing the tiers defined in the `[package.metadata.document-features.tiers]`
+   table of Cargo.toml, with a row per documented feature and a column per tier. The features
+   enabled by the features of a tier are also part of it.
+   ```toml
+   [package.metadata.document-features.tiers]
+   free = ["std"]
+   pro = ["std", "enterprise"]
+   ```
+ - `"text"`: plain text without markdown, for example to be shown in a terminal or in the output
+   of `--help`, with a line such as `foo (default) - description` per feature. The lines of the
+   descriptions are joined into a single line. The headings of the `#! ` comments are in
+   uppercase, and the code spans, emphasis and links are replaced by their text.
+ - `"html"`: a `<ul>` list, for documentation written in HTML blocks where markdown is not
+   rendered. The names of the features are in `<code>` and the default marker in `<em>`.
+   The `#! ` comments are turned into `<p>` paragraphs and `<h1>`…`<h6>` headings between the
+   lists. The descriptions are escaped and kept as is, without interpreting their markdown.
+ - `"dl"`: like `"html"`, but with a `<dl>` definition list where each feature is a `<dt>` term,
+   with its default marker, followed by its description in a `<dd>`.
+   With **`data_attributes=`** `true`, the `<li>` or `<dt>` element of each feature has the
+   attributes `data-default` and `data-optional-dep` (`"true"` or `"false"`), and `data-since`
+   with the value of the `@since` tag (see [Tags](#tags)), so that scripts can filter or sort the
+   features. With **`accessible=`** `true`, the `<ul>` lists have `role="list"`, so that screen
+   readers still announce them as lists when the stylesheet removes their bullets, and the default
+   marker has an `aria-label`. With **`wrapper_class=`**, the whole output is wrapped in a `<div>` with this class,
+   so that the stylesheet of the documentation can target it.
+ - `"rst"`: a [reStructuredText](https://docutils.sourceforge.io/rst.html) bullet list, for
+   documentation generated with tools such as Sphinx. The names of the features are inline
+   literals, and the headings of the `#! ` comments are turned into underlined section titles.
+ - `"table"`: a markdown table with the columns *Feature*, *Default* and *Description*, which
+   reads better for long lists. The `#! ` comments are written between the tables, so each group
+   of features has its own table. The lines of the descriptions are joined, and their `|` are
+   escaped.
+
+Not all the arguments apply to all the formats, and the macro reports an error when an argument
+is used with a format that doesn't support it. The arguments that shape the line of each feature,
+`template`, `line_template`, `description_template`, `separator`, `bullet`, `join`,
+`max_description_chars`, `normalize_case`, `default_icon`, `default_marker_position`,
+`default_marker_emphasis`, `mark_dependencies`, `annotate_target`, `show_source`,
+`group_separator` and `indent_dependents`, are only supported by the `"markdown"` format.
+`feature_label` is supported by `"markdown"`, `"table"` and `"tiers"`, `default_feature_label`
+by `"markdown"` and `"table"`, and `default_label` by `"markdown"`, `"html"`, `"dl"` and
+`"rst"`. `toc` and `anchors` are supported by `"markdown"`, `"table"`, `"html"` and `"dl"`,
+`cross_link` by `"markdown"` and `"table"`, and `legend` by `"markdown"` only.
+`data_attributes`, `accessible` and `wrapper_class` are only supported by `"html"` and `"dl"`,
+and the `badge_*` arguments by `"badges"`. `profile`, `prefix_filter`, `include` and `exclude`
+are supported by all the formats except `"mermaid"`, which graphs all the features. The `@icon`,
+`@alternative-to`, `@default-on`, `@non-additive` and `@enables-item` tags (see [Tags](#tags))
+are only rendered by the `"markdown"` format.
+
+### Default features
+
+ - **`no_default_note=`** *(bool)*: start the output with a note listing all the features
+   that are turned off by `--no-default-features`.
+ - **`note_no_defaults=`** *(bool)*: start the output with the note *"No features are enabled by
+   default."* when the `default` feature is missing or empty.
+ - **`default_marker_position=`**: `"suffix"` (the default) puts the *(enabled by default)* marker
+   after the feature name, `"prefix"` puts it before.
+ - **`show_default=`** *(bool)*: `false` to leave out the *(enabled by default)* marker.
+ - **`default_label=`**: the text of the marker instead of *(enabled by default)*, such as
+   `default_label = "🔧 default"`. It is used as is, like `feature_label`.
+ - **`default_feature_label=`**: the format of the names of the features enabled by default,
+   like `feature_label` (see [Customization](#customization)), which is used for the other
+   features. Combined with `show_default = false`, the default features can be told apart by
+   their style instead of a marker, e.g. `default_feature_label = "**`{feature}`** ✓"`.
+ - **`default_marker_emphasis=`**: how the marker is emphasized: `"italic"`, `"bold"`, `"code"`,
+   or `"none"`. The default is `"italic"`, or `"none"` with `default_label`.
+ - **`default_icon=`**: a symbol such as `"★"` put at the start of the line of the features
+   enabled by default, before their name, in addition to the marker. This is independent of
+   `show_default`, so `show_default = false` keeps only the icon.
+ - **`warn_unknown_defaults=`** *(bool)*: report an error if the `default` feature references a
+   name that is neither a feature nor a documented optional dependency.
+ - **`validate_dep_refs=`** *(bool)*: report an error if a feature enables a `dep:` dependency that
+   is not declared, or that is not optional. Cargo reports these errors too, but only when
+   building the crate with the feature.
+ - **`duplicate_default=`**: what to do when the `default` feature is defined more than once,
+   which Cargo doesn't accept: `"error"` (the default) reports an error, `"last"` uses the
+   last definition.
+ - **`require_default_docs=`** *(bool)*: report an error listing the features of the `default`
+   feature that are not documented with a `## ` comment.
+ - **`require_all_documented=`** *(bool)*: report an error listing all the features of the
+   `[features]` table, except `default`, that are not documented with a `## ` comment.
+ - **`include_undocumented=`**: `true` to also list the features of the `[features]` table that
+   have no `## ` comment, without description, or a string literal used as their description,
+   such as `include_undocumented = "*Not documented*"`. By default they are not listed.
+
+### Comments position
+
+ - **`comment_position=`**: `"above"` (the default) when the `## ` comments are written above
+   the feature or optional dependency they document, or `"below"` when they are written below it.
+   With `"below"`, all the `## ` comments document the feature that precedes them.
+ - **`strict_comment_blocks=`** *(bool)*: report an error when a block of `## ` comments is
+   interrupted by another comment such as `# note`, instead of ignoring that comment.
+
+### Sections
+
+ - **`section_intro=`** *(bool)*: allow `## ` comments right before the `[features]` or a
+   `[dependencies]` table header. They are then rendered like `#! ` comments, as an introduction
+   to the section. Without this option, such comments are an error.
+ - **`group_separator=`**: markdown inserted between two groups of features, that is before
+   each `#! ` comment that follows a feature, e.g. `group_separator = "---"` for a horizontal rule.
+ - **`anchors=`** *(bool)*: add an anchor such as `<a id="feature-foo"></a>` at the start of the
+   line of each feature, so that other parts of the documentation can link to it with
+   `[see foo](#feature-foo)`. The id is `feature-` followed by the name of the feature in
+   lowercase, where the characters other than letters and digits are replaced by `-`. If two
+   features would get the same id, `-2`, `-3`, ... is added to the next ones. With
+   `workspace = true`, the name of the crate comes before the name of the feature, such as
+   `feature-my-crate-foo`. This is not supported by the `"rst"` and `"text"` formats, which
+   report an error.
+ - **`toc=`** *(bool)*: start the list with a table of contents linking to each feature, for
+   crates with many features. This implies `anchors = true`, and is not supported by the `"rst"`
+   and `"text"` formats either. The headings of the `#! ` comments are part of the table of
+   contents too, with an anchor such as `section-experimental` for `#! ### Experimental`. The id
+   doesn't depend on the level of the heading, and `-2`, `-3`, ... is added to the ids of the
+   headings with the same text.
+ - **`cross_link=`** *(bool)*: turn the names of features written in code spans in the comments,
+   such as `` `tls` ``, into links to these features. This implies `anchors = true`, and is only
+   supported by the `"markdown"` and `"table"` formats.
+ - **`bullet=`**: the marker that starts the line of each feature (default: `"*"`), e.g.
+   `bullet = "-"` to match the style of the surrounding documentation.
+ - **`mark_dependencies=`** *(bool)*: add *(dependency)* after the optional dependencies, to tell
+   them apart from the features when they are in the same list.
+ - **`document_dev_deps=`** *(bool)*: move the optional dependencies that are only declared in
+   `[dev-dependencies]` tables to the end of the list, under a *Development dependencies*
+   heading. Otherwise they are listed with the other features, in the order of Cargo.toml.
+ - **`group_non_additive=`** *(bool)*: move the features with the `@non-additive` tag to the end
+   of the list, under a *Non-additive features (use with care)* heading.
+ - **`annotate_target=`** *(bool)*: add a note such as *(only for `cfg(windows)`)* after the
+   optional dependencies that are only declared in `[target.'cfg(...)'.dependencies]` tables.
+ - **`show_generated_note=`**: `true` to end the list with the note
+   *"Feature documentation generated from Cargo.toml."*, so that readers know that it should be
+   edited in Cargo.toml, or a string literal to use as the note instead.
+ - **`implicit_features_note=`**: `true` to start the list with the note *"This crate has no
+   Cargo features; the following optional dependencies act as implicit features."* when all the
+   documented features are optional dependencies, or a string literal to use as the note instead.
+ - **`footers=`** *(bool)*: `false` to leave out the `#! ` comments after the last feature.
+ - **`layout=`**: the order of the parts of the output, as an array of `"legend"` (see `legend`),
+   `"summary"` (see `requirements_summary`), `"default-note"` (see `no_default_note`), `"toc"`
+   (see `toc`), `"features"` (the list of features), `"footers"` (the `#! ` comments after the
+   last feature), and `"generated-note"` (see `show_generated_note`). This is the default order.
+   The parts that are not in the array are left out, e.g. `layout = ["features"]` only keeps the list of features.
+ - **`indent_dependents=`** *(bool)*: nest each feature under the first documented feature that it
+   enables, since enabling it also enables that feature. Features that come right after `#! `
+   comments start a group and are not nested.
+ - **`requirements_summary=`** *(bool)*: start the list with a summary of the `@requires`,
+   `@platform` and `@msrv` tags (see [Tags](#tags)) of all the features, such as
+   *"Some features require: nightly, unix, Rust 1.70."*
+ - **`legend=`** *(bool)*: start the list with a legend of the markers used in the output, such as
+   *(enabled by default)* or *(experimental)*. Only the markers of the listed features are part of
+   the legend. This is only supported by the `"markdown"` format.
+
+### Sorting
+
+ - **`sort=`**: `"declaration"` (the default) keeps the features in the order of Cargo.toml,
+   `"stability"` groups them by their `@stability` tag (see [Tags](#tags)): stable features
+   first, then experimental and deprecated features, each group under a heading.
+   The `#! ` comments are not part of the output in this case.
+   `"alphabetical"` sorts the features by name within each section, so that the `#! ` comments
+   stay at the start of their section.
+
+*/
+#![doc = self_test!(/**
+[features]
+#! ### Backends
+## The software renderer
+software = []
+## The OpenGL renderer
+gl = []
+#! ### Platforms
+## Support for Wayland
+wayland = []
+## Support for X11
+x11 = []
+## Support for Android
+android = []
+*/, sort = "alphabetical", feature_label = "`{feature}`"
+=>
+    /**
+#### Backends
+* `gl` —  The OpenGL renderer
+* `software` —  The software renderer
+
+#### Platforms
+* `android` —  Support for Android
+* `wayland` —  Support for Wayland
+* `x11` —  Support for X11
+*/
+)]
+/*!
+
+### Filtering
+
+ - **`prefix_filter=`**: only document the features whose name starts with the given prefix.
+   The `#! ` comments whose features are all filtered out are not part of the output.
+ - **`include=`**: only document the features whose name matches one of the given patterns,
+   separated by commas, where `*` matches any sequence of characters and `?` any single
+   character, e.g. `include = "api-*, std"`. Like with `prefix_filter`, the `#! ` comments whose
+   features are all filtered out are not part of the output.
+ - **`exclude=`**: do not document the features whose name matches one of the given patterns,
+   with the same syntax as `include`, e.g. `exclude = "internal-*, __*"` to hide the features
+   that are only used to build the crate.
+ - **`profile=`**: only document the features of a profile. The profiles are lists of features
+   defined in the `[package.metadata.document-features.profiles]` table of Cargo.toml:
+   ```toml
+   [package.metadata.document-features.profiles]
+   minimal = ["std", "log"]
+   ```
+
+### Other manifests
+
+ - **`manifest_path=`**: the path of the manifest to document instead of the Cargo.toml of the
+   crate, relative to the directory of the crate, e.g. `manifest_path = "../other/Cargo.toml"` to
+   document the features of another crate of the workspace. Like for Cargo.toml, the `.orig` file
+   next to the manifest is used if the manifest has no doc comments.
+ - **`workspace=`**: when `true`, the manifest is the root of a workspace, and the features of all
+   the `members` of its `[workspace]` table are documented, each under a heading with the name of
+   the crate. The members without doc comments are skipped. For example, in a crate that
+   re-exports the other crates of its workspace:
+   ```rust,ignore
+   #![doc = document_features::document_features!(manifest_path = "../Cargo.toml", workspace = true)]
+   ```
+
+### Included files
+
+A manifest assembled from several files can include the features of another file with a
+`# @include-features: path` comment, where `path` is relative to the directory of Cargo.toml.
+The comment is replaced by the content of the file before the features are documented.
+
+```toml
+[features]
+# @include-features: manifest/features.toml
+```
+
+### Languages
+
+The lines of the `## ` and `#! ` comments can start with a language tag such as `[en]` or `[de]`.
+
+ - **`lang=`**: the language of the comments to use (default: `"en"`). For each comment, if
+   there are lines in that language, only these are used. Otherwise the lines without language
+   tag are used, or the `[en]` lines if there are none.
+
+```toml
+[features]
+## [en] Enable the network support
+## [de] Aktiviert die Netzwerkunterstützung
+network = []
+```
+
+### Descriptions
+
+ - **`join=`**: how the lines of multi-line descriptions are joined: `"newline"` (the default)
+   keeps the line breaks, `"space"` joins all the lines into a single paragraph.
+ - **`max_description_chars=`** *(integer)*: descriptions longer than this number of characters
+   are truncated at a word boundary and followed by `…`. Inline code spans are never cut.
+ - **`description_template=`**: a string in which `{doc}` is replaced by the description and
+   `{name}` by the name of the feature. It replaces the ` — description` part that follows the
+   feature label, e.g. `description_template = ": {doc} (`--features {name}`)"`.
+ - **`separator=`**: the text between the feature label and the description instead of the em
+   dash, e.g. `separator = ":"` for `` **`foo`**: description ``. It is left out for the features
+   without description.
+ - **`normalize_case=`** *(bool)*: start the descriptions with a capital letter and end them with
+   a period. Descriptions starting with markdown, such as a code span, are not capitalized.
+ - **`show_source=`** *(bool)*: add below each feature a collapsed block with the lines of
+   `Cargo.toml` that declare it.
+ - **`min_description_words=`** and **`max_description_words=`** *(integer)*: report an error
+   listing the features whose description has fewer or more words than this, to keep the
+   documentation of all the features at a consistent level of detail.
+
+### Defaults in Cargo.toml
+
+The arguments can also be set in the `[package.metadata.document-features]` table of Cargo.toml,
+so that all the invocations of the macro in the crate share them. The arguments given to the
+macro take precedence over the ones of this table.
+
+```toml
+[package.metadata.document-features]
+sort = "stability"
+feature_label = "**`{feature}`**"
+```
+
 ## Compatibility
 
 The minimum Rust version required to use this crate is Rust 1.54 because of the
@@ -144,20 +518,15 @@ features = ["document-features"]
 ```
  */
 
-#[cfg(not(feature = "default"))]
-compile_error!(
-    "The feature `default` must be enabled to ensure \
-    forward compatibility with future version of this crate"
-);
-
 extern crate proc_macro;
 
-use proc_macro::{TokenStream, TokenTree};
+use proc_macro::{Delimiter, TokenStream, TokenTree};
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 fn error(e: &str) -> TokenStream {
@@ -166,7 +535,7 @@ fn error(e: &str) -> TokenStream {
 
 fn compile_error(msg: &str, tt: Option<TokenTree>) -> TokenStream {
     let span = tt.as_ref().map_or_else(proc_macro::Span::call_site, TokenTree::span);
-    use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing};
+    use proc_macro::{Group, Ident, Literal, Punct, Spacing};
     use std::iter::FromIterator;
     TokenStream::from_iter(vec![
         TokenTree::Ident(Ident::new("compile_error", span)),
@@ -189,847 +558,6762 @@ fn compile_error(msg: &str, tt: Option<TokenTree>) -> TokenStream {
     ])
 }
 
-#[derive(Default)]
-struct Args {
-    feature_label: Option<String>,
+/// The kind of output generated by the macro, selected with the `format` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Format {
+    /// A markdown list of features (the default)
+    Markdown,
+    /// A single line of shields.io badges
+    Badges,
+    /// A Mermaid graph of the features enabled by each feature
+    Mermaid,
+    /// A table of the features in each tier
+    Tiers,
+    /// Plain text without markdown
+    Text,
+    /// An HTML list of features
+    Html,
+    /// A markdown table of features
+    Table,
+    /// An HTML definition list of features
+    Dl,
+    /// A reStructuredText list of features
+    Rst,
 }
 
-fn parse_args(input: TokenStream) -> Result<Args, TokenStream> {
-    let mut token_trees = input.into_iter().fuse();
-
-    // parse the key, ensuring that it is the identifier `feature_label`
-    match token_trees.next() {
-        None => return Ok(Args::default()),
-        Some(TokenTree::Ident(ident)) if ident.to_string() == "feature_label" => (),
-        tt => return Err(compile_error("expected `feature_label`", tt)),
+impl Default for Format {
+    fn default() -> Self {
+        Format::Markdown
     }
+}
 
-    // parse a single equal sign `=`
-    match token_trees.next() {
-        Some(TokenTree::Punct(p)) if p.as_char() == '=' => (),
-        tt => return Err(compile_error("expected `=`", tt)),
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "markdown" => Some(Format::Markdown),
+            "badges" => Some(Format::Badges),
+            "mermaid" => Some(Format::Mermaid),
+            "tiers" => Some(Format::Tiers),
+            "text" => Some(Format::Text),
+            "html" => Some(Format::Html),
+            "table" => Some(Format::Table),
+            "dl" => Some(Format::Dl),
+            "rst" => Some(Format::Rst),
+            _ => None,
+        }
     }
 
-    // parse the value, ensuring that it is a string literal containing the substring `"{feature}"`
-    let feature_label;
-    if let Some(tt) = token_trees.next() {
-        match litrs::StringLit::<String>::try_from(&tt) {
-            Ok(string_lit) if string_lit.value().contains("{feature}") => {
-                feature_label = string_lit.value().to_string()
-            }
-            _ => {
-                return Err(compile_error(
-                    "expected a string literal containing the substring \"{feature}\"",
-                    Some(tt),
-                ))
-            }
+    fn name(self) -> &'static str {
+        match self {
+            Format::Markdown => "markdown",
+            Format::Badges => "badges",
+            Format::Mermaid => "mermaid",
+            Format::Tiers => "tiers",
+            Format::Text => "text",
+            Format::Html => "html",
+            Format::Table => "table",
+            Format::Dl => "dl",
+            Format::Rst => "rst",
         }
-    } else {
-        return Err(compile_error(
-            "expected a string literal containing the substring \"{feature}\"",
-            None,
-        ));
     }
+}
 
-    // ensure there is nothing left after the format string
-    if let tt @ Some(_) = token_trees.next() {
-        return Err(compile_error("unexpected token after the format string", tt));
+/// How the lines of a multi-line description are joined, selected with the `join` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Join {
+    /// Keep the line breaks (the default)
+    Newline,
+    /// Join all the lines into a single paragraph
+    Space,
+}
+
+impl Default for Join {
+    fn default() -> Self {
+        Join::Newline
     }
+}
 
-    Ok(Args { feature_label: Some(feature_label) })
+/// Where the `##` comments are relative to the feature they document,
+/// selected with the `comment_position` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum CommentPosition {
+    /// Comments are above the feature (the default)
+    Above,
+    /// Comments are below the feature
+    Below,
 }
 
-/// Produce a literal string containing documentation extracted from Cargo.toml
-///
-/// See the [crate] documentation for details
-#[proc_macro]
-pub fn document_features(tokens: TokenStream) -> TokenStream {
-    parse_args(tokens)
-        .and_then(|args| document_features_impl(&args))
-        .unwrap_or_else(std::convert::identity)
+impl Default for CommentPosition {
+    fn default() -> Self {
+        CommentPosition::Above
+    }
 }
 
-fn document_features_impl(args: &Args) -> Result<TokenStream, TokenStream> {
-    let path = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-    let mut cargo_toml = std::fs::read_to_string(Path::new(&path).join("Cargo.toml"))
-        .map_err(|e| error(&format!("Can't open Cargo.toml: {:?}", e)))?;
+/// The order of the features in the output, selected with the `sort` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Sort {
+    /// The order in which they are declared in Cargo.toml (the default)
+    Declaration,
+    /// Grouped by their `@stability` tag
+    Stability,
+    /// By name, within each section
+    Alphabetical,
+}
 
-    if !has_doc_comments(&cargo_toml) {
-        // On crates.io, Cargo.toml is usually "normalized" and stripped of all comments.
-        // The original Cargo.toml has been renamed Cargo.toml.orig
-        if let Ok(orig) = std::fs::read_to_string(Path::new(&path).join("Cargo.toml.orig")) {
-            if has_doc_comments(&orig) {
-                cargo_toml = orig;
-            }
+impl Default for Sort {
+    fn default() -> Self {
+        Sort::Declaration
+    }
+}
+
+/// Where the default marker goes relative to the feature name,
+/// selected with the `default_marker_position` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum MarkerPosition {
+    /// After the feature name (the default)
+    Suffix,
+    /// Before the feature name
+    Prefix,
+}
+
+impl Default for MarkerPosition {
+    fn default() -> Self {
+        MarkerPosition::Suffix
+    }
+}
+
+/// How the default marker is emphasized, selected with the `default_marker_emphasis` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Emphasis {
+    /// `*text*`
+    Italic,
+    /// `**text**`
+    Bold,
+    /// `` `text` ``
+    Code,
+    /// No emphasis
+    None,
+}
+
+/// A part of the markdown output, in the order given by the `layout` argument
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Section {
+    /// The legend of `legend`
+    Legend,
+    /// The summary of `requirements_summary`
+    Summary,
+    /// The note of `no_default_note`
+    DefaultNote,
+    /// The table of contents of `toc`
+    Toc,
+    /// The list of features
+    Features,
+    /// The `#!` comments after the last feature
+    Footers,
+    /// The note of `show_generated_note`
+    GeneratedNote,
+}
+
+/// The order of the sections when there is no `layout` argument
+const DEFAULT_LAYOUT: &[Section] = &[
+    Section::Legend,
+    Section::Summary,
+    Section::DefaultNote,
+    Section::Toc,
+    Section::Features,
+    Section::Footers,
+    Section::GeneratedNote,
+];
+
+impl Section {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "legend" => Some(Section::Legend),
+            "summary" => Some(Section::Summary),
+            "default-note" => Some(Section::DefaultNote),
+            "toc" => Some(Section::Toc),
+            "features" => Some(Section::Features),
+            "footers" => Some(Section::Footers),
+            "generated-note" => Some(Section::GeneratedNote),
+            _ => None,
         }
     }
+}
 
-    let result = process_toml(&cargo_toml, args).map_err(|e| error(&e))?;
-    Ok(std::iter::once(proc_macro::TokenTree::from(proc_macro::Literal::string(&result))).collect())
+impl Emphasis {
+    fn wrap(self, text: &str) -> String {
+        match self {
+            Emphasis::Italic => format!("*{}*", text),
+            Emphasis::Bold => format!("**{}**", text),
+            Emphasis::Code => format!("`{}`", text),
+            Emphasis::None => text.into(),
+        }
+    }
 }
 
-/// Check if the Cargo.toml has comments that looks like doc comments.
-fn has_doc_comments(cargo_toml: &str) -> bool {
-    let mut lines = cargo_toml.lines().map(str::trim);
-    while let Some(line) = lines.next() {
-        if line.starts_with("## ") || line.starts_with("#! ") {
-            return true;
+/// The note added by `show_generated_note = true`
+const GENERATED_NOTE: &str = "Feature documentation generated from Cargo.toml.";
+
+/// The note added by `implicit_features_note = true`
+const IMPLICIT_FEATURES_NOTE: &str = "This crate has no Cargo features; the following optional \
+                                      dependencies act as implicit features.";
+
+#[derive(Clone, Default)]
+struct Args {
+    feature_label: Option<String>,
+    format: Format,
+    badge_url: Option<String>,
+    badge_color: Option<String>,
+    badge_default_color: Option<String>,
+    max_description_chars: Option<usize>,
+    no_default_note: bool,
+    join: Join,
+    prefix_filter: Option<String>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    comment_position: CommentPosition,
+    sort: Sort,
+    section_intro: bool,
+    default_marker_position: MarkerPosition,
+    profile: Option<String>,
+    lang: Option<String>,
+    warn_unknown_defaults: bool,
+    description_template: Option<String>,
+    show_source: bool,
+    group_separator: Option<String>,
+    strict_comment_blocks: bool,
+    mark_dependencies: bool,
+    default_label: Option<String>,
+    default_marker_emphasis: Option<Emphasis>,
+    annotate_target: bool,
+    require_default_docs: bool,
+    require_all_documented: bool,
+    /// The description of the undocumented features, which are otherwise not listed
+    include_undocumented: Option<String>,
+    normalize_case: bool,
+    generated_note: Option<String>,
+    requirements_summary: bool,
+    /// `footers = false`
+    hide_footers: bool,
+    /// `duplicate_default = "last"`
+    last_default: bool,
+    layout: Option<Vec<Section>>,
+    indent_dependents: bool,
+    /// `show_default = false`
+    hide_default: bool,
+    template: Option<String>,
+    line_template: Option<String>,
+    bullet: Option<String>,
+    default_icon: Option<String>,
+    min_description_words: Option<usize>,
+    max_description_words: Option<usize>,
+    implicit_features_note: Option<String>,
+    separator: Option<String>,
+    default_feature_label: Option<String>,
+    note_no_defaults: bool,
+    data_attributes: bool,
+    accessible: bool,
+    toc: bool,
+    anchors: bool,
+    document_dev_deps: bool,
+    cross_link: bool,
+    manifest_path: Option<String>,
+    wrapper_class: Option<String>,
+    workspace: bool,
+    /// The name of the crate in the anchor ids, set for each member with `workspace`
+    anchor_prefix: Option<String>,
+    group_non_additive: bool,
+    legend: bool,
+    validate_dep_refs: bool,
+}
+
+/// The value of an argument, given to the macro or in `[package.metadata.document-features]`
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    String(String),
+    Bool(bool),
+    Integer(usize),
+    Array(Vec<Value>),
+    /// Anything else, which is never a valid value
+    Other,
+}
+
+impl Value {
+    fn from_token(tt: &TokenTree) -> Value {
+        if let Ok(s) = litrs::StringLit::<String>::try_from(tt) {
+            return Value::String(s.value().to_string());
         }
-        let before_coment = line.split_once('#').map_or(line, |(before, _)| before);
-        if line.starts_with("#") {
-            continue;
+        if let Ok(b) = litrs::BoolLit::try_from(tt) {
+            return Value::Bool(b.value());
         }
-        if let Some((_, mut quote)) = before_coment.split_once("\"\"\"") {
-            loop {
-                // skip slashes.
-                if let Some((_, s)) = quote.split_once('\\') {
-                    quote = s.strip_prefix('\\').or_else(|| s.strip_prefix('"')).unwrap_or(s);
-                    continue;
+        if let Ok(i) = litrs::IntegerLit::try_from(tt) {
+            return i.value().map_or(Value::Other, Value::Integer);
+        }
+        match tt {
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket => {
+                let mut items = Vec::new();
+                let mut token_trees = g.stream().into_iter();
+                while let Some(tt) = token_trees.next() {
+                    items.push(Value::from_token(&tt));
+                    match token_trees.next() {
+                        None => break,
+                        Some(TokenTree::Punct(p)) if p.as_char() == ',' => (),
+                        _ => return Value::Other,
+                    }
                 }
-                // skip quotes.
-                if let Some((_, out_quote)) = quote.split_once("\"\"\"") {
-                    let out_quote = out_quote.trim_start_matches('"');
-                    let out_quote =
-                        out_quote.split_once('#').map_or(out_quote, |(before, _)| before);
-                    if let Some((_, q)) = out_quote.split_once("\"\"\"") {
-                        quote = q;
-                        continue;
+                Value::Array(items)
+            }
+            _ => Value::Other,
+        }
+    }
+
+    /// Parse a TOML value: a string, a boolean, an integer or an array of these
+    fn from_toml(value: &str) -> Value {
+        let value = value.trim();
+        if let Some(s) = value.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+            return Value::String(s.into());
+        }
+        if let Some(s) = value.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            let mut result = String::new();
+            let mut chars = s.chars();
+            while let Some(c) = chars.next() {
+                result.push(if c != '\\' {
+                    c
+                } else {
+                    match chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some(c @ '"') | Some(c @ '\\') => c,
+                        _ => return Value::Other,
                     }
-                    break;
-                };
-                match lines.next() {
-                    Some(l) => quote = l,
-                    None => return false,
+                });
+            }
+            return Value::String(result);
+        }
+        if let Some(s) = value.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let mut items = Vec::new();
+            let mut start = 0;
+            let mut quote = None;
+            let mut last_slash = false;
+            for (idx, c) in s.char_indices() {
+                match quote {
+                    _ if last_slash => last_slash = false,
+                    Some('"') if c == '\\' => last_slash = true,
+                    Some(q) if c == q => quote = None,
+                    Some(_) => (),
+                    None if c == '"' || c == '\'' => quote = Some(c),
+                    None if c == ',' => {
+                        items.push(Value::from_toml(&s[start..idx]));
+                        start = idx + 1;
+                    }
+                    None => (),
                 }
             }
+            // a trailing comma is allowed
+            if !s[start..].trim().is_empty() {
+                items.push(Value::from_toml(&s[start..]));
+            }
+            return Value::Array(items);
+        }
+        match value {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => value.replace('_', "").parse().map_or(Value::Other, Value::Integer),
         }
     }
-    false
 }
 
-#[test]
-fn test_has_doc_coment() {
-    assert!(has_doc_comments("foo\nbar\n## comment\nddd"));
-    assert!(!has_doc_comments("foo\nbar\n#comment\nddd"));
-    assert!(!has_doc_comments(
-        r#"
-[[package.metadata.release.pre-release-replacements]]
-exactly = 1 # not a doc comment
-file = "CHANGELOG.md"
-replace = """
-<!-- next-header -->
-## [Unreleased] - ReleaseDate
-"""
-search = "<!-- next-header -->"
-array = ["""foo""", """
-bar""", """eee
-## not a comment
-"""]
-    "#
-    ));
-    assert!(has_doc_comments(
-        r#"
-[[package.metadata.release.pre-release-replacements]]
-exactly = 1 # """
-file = "CHANGELOG.md"
-replace = """
-<!-- next-header -->
-## [Unreleased] - ReleaseDate
-"""
-search = "<!-- next-header -->"
-array = ["""foo""", """
-bar""", """eee
-## not a comment
-"""]
-## This is a comment
-feature = "45"
-        "#
-    ));
-
-    assert!(!has_doc_comments(
-        r#"
-[[package.metadata.release.pre-release-replacements]]
-value = """" string \"""
-## within the string
-\""""
-another_string = """"" # """
-## also within"""
-"#
-    ));
+/// The reason why an argument can't be applied
+enum ArgError {
+    /// There is no argument with this name
+    Unknown,
+    /// The value is not valid, the message says what was expected
+    Invalid(String),
+}
 
-    assert!(has_doc_comments(
-        r#"
-[[package.metadata.release.pre-release-replacements]]
-value = """" string \"""
-## within the string
-\""""
-another_string = """"" # """
-## also within"""
-## out of the string
-foo = bar
-        "#
-    ));
+/// Parse the value of an argument with `f`, or produce an error saying what was `expected`
+fn parse_value<T>(
+    value: Option<&Value>,
+    expected: &str,
+    f: impl FnOnce(&Value) -> Option<T>,
+) -> Result<T, ArgError> {
+    value.and_then(f).ok_or_else(|| ArgError::Invalid(format!("expected {}", expected)))
 }
 
-fn dependents(
-    feature_dependencies: &HashMap<String, Vec<String>>,
-    feature: &str,
-    collected: &mut HashSet<String>,
-) {
-    if collected.contains(feature) {
-        return;
-    }
-    collected.insert(feature.to_string());
-    if let Some(dependencies) = feature_dependencies.get(feature) {
-        for dependency in dependencies {
-            dependents(feature_dependencies, dependency, collected);
-        }
+fn string_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        _ => None,
     }
 }
 
-fn parse_feature_deps<'a>(
-    s: &'a str,
-    dep: &str,
-) -> Result<impl Iterator<Item = String> + 'a, String> {
-    Ok(s.trim()
-        .strip_prefix('[')
-        .and_then(|r| r.strip_suffix(']'))
-        .ok_or_else(|| format!("Parse error while parsing dependency {}", dep))?
-        .split(',')
-        .map(|d| d.trim().trim_matches(|c| c == '"' || c == '\'').trim().to_string())
-        .filter(|d: &String| !d.is_empty()))
+fn parse_string(value: Option<&Value>) -> Result<String, ArgError> {
+    parse_value(value, "a string literal", string_value)
 }
 
-fn process_toml(cargo_toml: &str, args: &Args) -> Result<String, String> {
-    // Get all lines between the "[features]" and the next block
-    let mut lines = cargo_toml
-        .lines()
-        .map(str::trim)
-        // and skip empty lines and comments that are not docs comments
-        .filter(|l| {
-            !l.is_empty() && (!l.starts_with('#') || l.starts_with("##") || l.starts_with("#!"))
-        });
-    let mut top_comment = String::new();
-    let mut current_comment = String::new();
-    let mut features = vec![];
-    let mut default_features = HashSet::new();
-    let mut current_table = "";
-    let mut dependencies = HashMap::new();
-    while let Some(line) = lines.next() {
-        if let Some(x) = line.strip_prefix("#!") {
-            if !x.is_empty() && !x.starts_with(' ') {
-                continue; // it's not a doc comment
-            }
-            if !current_comment.is_empty() {
-                return Err("Cannot mix ## and #! comments between features.".into());
-            }
-            if top_comment.is_empty() && !features.is_empty() {
-                top_comment = "\n".into();
-            }
-            writeln!(top_comment, "{}", x).unwrap();
-        } else if let Some(x) = line.strip_prefix("##") {
-            if !x.is_empty() && !x.starts_with(' ') {
-                continue; // it's not a doc comment
+fn parse_bool(value: Option<&Value>) -> Result<bool, ArgError> {
+    parse_value(value, "`true` or `false`", |v| match v {
+        Value::Bool(b) => Some(*b),
+        _ => None,
+    })
+}
+
+fn parse_integer(value: Option<&Value>) -> Result<usize, ArgError> {
+    parse_value(value, "an integer literal", |v| match v {
+        Value::Integer(i) => Some(*i),
+        _ => None,
+    })
+}
+
+/// Parse a comma separated list of glob patterns
+fn parse_patterns(value: Option<&Value>) -> Result<Vec<String>, ArgError> {
+    let patterns = parse_string(value)?;
+    let patterns = patterns.split(',').map(str::trim).filter(|p| !p.is_empty());
+    let patterns = patterns.map(String::from).collect::<Vec<_>>();
+    if patterns.is_empty() {
+        return Err(ArgError::Invalid("expected a comma separated list of patterns".into()));
+    }
+    Ok(patterns)
+}
+
+/// Parse an array of string literals such as `["a", "b"]`, each parsed with `f`
+fn parse_list<T>(
+    value: Option<&Value>,
+    expected: &str,
+    f: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, ArgError> {
+    match value {
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|v| parse_value(Some(v), expected, |v| string_value(v).and_then(|s| f(&s))))
+            .collect(),
+        _ => Err(ArgError::Invalid(format!("expected an array of {}", expected))),
+    }
+}
+
+/// Parse the arguments of the macro into the name and the value of each argument
+fn parse_args(
+    input: TokenStream,
+) -> Result<Vec<(proc_macro::Ident, Option<TokenTree>)>, TokenStream> {
+    let mut token_trees = input.into_iter().fuse();
+    let mut args = Vec::new();
+    let mut seen = HashSet::new();
+
+    loop {
+        // parse the key
+        let key = match token_trees.next() {
+            None => return Ok(args),
+            Some(TokenTree::Ident(ident)) => ident,
+            tt => {
+                return Err(compile_error("expected an argument name such as `feature_label`", tt))
             }
-            writeln!(current_comment, " {}", x).unwrap();
-        } else if let Some(table) = line.strip_prefix('[') {
-            current_table = table
-                .split_once(']')
-                .map(|(t, _)| t.trim())
-                .ok_or_else(|| format!("Parse error while parsing line: {}", line))?;
-            if !current_comment.is_empty() {
-                #[allow(clippy::unnecessary_lazy_evaluations)]
-                let dep = current_table
-                    .rsplit_once('.')
-                    .and_then(|(table, dep)| table.trim().ends_with("dependencies").then(|| dep))
-                    .ok_or_else(|| format!("Not a feature: `{}`", line))?;
-                features.push((
-                    dep.trim(),
-                    std::mem::take(&mut top_comment),
-                    std::mem::take(&mut current_comment),
-                ));
+        };
+        let name = key.to_string();
+        if !seen.insert(name.clone()) {
+            return Err(compile_error(&format!("duplicate argument `{}`", name), Some(key.into())));
+        }
+
+        // parse a single equal sign `=`
+        match token_trees.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '=' => (),
+            tt => return Err(compile_error("expected `=`", tt)),
+        }
+
+        args.push((key, token_trees.next()));
+
+        // ensure arguments are separated by a comma
+        match token_trees.next() {
+            None => return Ok(args),
+            Some(TokenTree::Punct(p)) if p.as_char() == ',' => (),
+            tt => return Err(compile_error("expected `,` after the value", tt)),
+        }
+    }
+}
+
+/// Set the argument `name` in `args`, ensuring that it is a known argument
+fn apply_arg(args: &mut Args, name: &str, value: Option<&Value>) -> Result<(), ArgError> {
+    match name {
+        // a string literal containing the substring `"{feature}"`
+        "feature_label" => {
+            args.feature_label = Some(parse_value(
+                value,
+                "a string literal containing the substring \"{feature}\"",
+                |v| string_value(v).filter(|s| s.contains("{feature}")),
+            )?)
+        }
+        "default_feature_label" => {
+            args.default_feature_label = Some(parse_value(
+                value,
+                "a string literal containing the substring \"{feature}\"",
+                |v| string_value(v).filter(|s| s.contains("{feature}")),
+            )?)
+        }
+        "template" => {
+            args.template = Some(parse_value(
+                value,
+                "a string literal containing the substring \"{feature}\"",
+                |v| string_value(v).filter(|s| s.contains("{feature}")),
+            )?)
+        }
+        "line_template" => {
+            args.line_template = Some(parse_value(
+                value,
+                "a string literal containing the substring \"{label}\" or \"{name}\"",
+                |v| string_value(v).filter(|s| s.contains("{label}") || s.contains("{name}")),
+            )?)
+        }
+        "format" => {
+            args.format = parse_value(
+                value,
+                "\"markdown\", \"badges\", \"mermaid\", \"tiers\", \"text\", \"html\", \"table\", \
+                 \"dl\" or \"rst\"",
+                |v| string_value(v).and_then(|s| Format::from_name(&s)),
+            )?
+        }
+        "badge_url" => args.badge_url = Some(parse_string(value)?),
+        "badge_color" => args.badge_color = Some(parse_string(value)?),
+        "badge_default_color" => args.badge_default_color = Some(parse_string(value)?),
+        "max_description_chars" => args.max_description_chars = Some(parse_integer(value)?),
+        "min_description_words" => args.min_description_words = Some(parse_integer(value)?),
+        "max_description_words" => args.max_description_words = Some(parse_integer(value)?),
+        "no_default_note" => args.no_default_note = parse_bool(value)?,
+        "note_no_defaults" => args.note_no_defaults = parse_bool(value)?,
+        "data_attributes" => args.data_attributes = parse_bool(value)?,
+        "accessible" => args.accessible = parse_bool(value)?,
+        "toc" => args.toc = parse_bool(value)?,
+        "anchors" => args.anchors = parse_bool(value)?,
+        "document_dev_deps" => args.document_dev_deps = parse_bool(value)?,
+        "group_non_additive" => args.group_non_additive = parse_bool(value)?,
+        "legend" => args.legend = parse_bool(value)?,
+        "validate_dep_refs" => args.validate_dep_refs = parse_bool(value)?,
+        "cross_link" => args.cross_link = parse_bool(value)?,
+        "manifest_path" => args.manifest_path = Some(parse_string(value)?),
+        "default_icon" => args.default_icon = Some(parse_string(value)?),
+        "wrapper_class" => args.wrapper_class = Some(parse_string(value)?),
+        "workspace" => args.workspace = parse_bool(value)?,
+        "prefix_filter" => args.prefix_filter = Some(parse_string(value)?),
+        "include" => args.include = Some(parse_patterns(value)?),
+        "exclude" => args.exclude = Some(parse_patterns(value)?),
+        "profile" => args.profile = Some(parse_string(value)?),
+        "lang" => args.lang = Some(parse_string(value)?),
+        "description_template" => args.description_template = Some(parse_string(value)?),
+        "warn_unknown_defaults" => args.warn_unknown_defaults = parse_bool(value)?,
+        "show_source" => args.show_source = parse_bool(value)?,
+        "group_separator" => args.group_separator = Some(parse_string(value)?),
+        "bullet" => args.bullet = Some(parse_string(value)?),
+        "separator" => args.separator = Some(parse_string(value)?),
+        "strict_comment_blocks" => args.strict_comment_blocks = parse_bool(value)?,
+        "mark_dependencies" => args.mark_dependencies = parse_bool(value)?,
+        "annotate_target" => args.annotate_target = parse_bool(value)?,
+        "require_default_docs" => args.require_default_docs = parse_bool(value)?,
+        "require_all_documented" => args.require_all_documented = parse_bool(value)?,
+        "normalize_case" => args.normalize_case = parse_bool(value)?,
+        "requirements_summary" => args.requirements_summary = parse_bool(value)?,
+        "footers" => args.hide_footers = !parse_bool(value)?,
+        "indent_dependents" => args.indent_dependents = parse_bool(value)?,
+        "show_default" => args.hide_default = !parse_bool(value)?,
+        "layout" => {
+            let expected = "\"legend\", \"summary\", \"default-note\", \"toc\", \"features\", \
+                            \"footers\" or \"generated-note\"";
+            let layout = parse_list(value, expected, Section::from_name)?;
+            for (i, section) in layout.iter().enumerate() {
+                if layout[..i].contains(section) {
+                    return Err(ArgError::Invalid(format!(
+                        "duplicate section {:?} in the layout",
+                        section
+                    )));
+                }
             }
-        } else if let Some((dep, rest)) = line.split_once('=') {
-            let dep = dep.trim().trim_matches('"');
-            let rest = get_balanced(rest, &mut lines)
-                .map_err(|e| format!("Parse error while parsing value {}: {}", dep, e))?;
-            if current_table == "features" {
-                if dep == "default" {
-                    default_features.extend(parse_feature_deps(&rest, dep)?);
-                } else {
-                    for d in parse_feature_deps(&rest, dep)? {
-                        dependencies
-                            .entry(dep.to_string())
-                            .or_insert_with(Vec::new)
-                            .push(d.clone());
+            args.layout = Some(layout);
+        }
+        "duplicate_default" => {
+            args.last_default =
+                parse_value(value, "\"error\" or \"last\"", |v| match string_value(v)?.as_str() {
+                    "error" => Some(false),
+                    "last" => Some(true),
+                    _ => None,
+                })?
+        }
+        "show_generated_note" => {
+            args.generated_note =
+                parse_value(value, "`true`, `false` or a string literal", |v| match v {
+                    Value::Bool(b) => Some(b.then(|| GENERATED_NOTE.to_string())),
+                    v => string_value(v).map(Some),
+                })?
+        }
+        "include_undocumented" => {
+            args.include_undocumented =
+                parse_value(value, "`true`, `false` or a string literal", |v| match v {
+                    Value::Bool(b) => Some(b.then(String::new)),
+                    v => string_value(v).map(Some),
+                })?
+        }
+        "implicit_features_note" => {
+            args.implicit_features_note =
+                parse_value(value, "`true`, `false` or a string literal", |v| match v {
+                    Value::Bool(b) => Some(b.then(|| IMPLICIT_FEATURES_NOTE.to_string())),
+                    v => string_value(v).map(Some),
+                })?
+        }
+        "sort" => {
+            args.sort =
+                parse_value(value, "\"declaration\", \"stability\" or \"alphabetical\"", |v| {
+                    match string_value(v)?.as_str() {
+                        "declaration" => Some(Sort::Declaration),
+                        "stability" => Some(Sort::Stability),
+                        "alphabetical" => Some(Sort::Alphabetical),
+                        _ => None,
                     }
+                })?
+        }
+        "section_intro" => args.section_intro = parse_bool(value)?,
+        "default_marker_position" => {
+            args.default_marker_position = parse_value(value, "\"prefix\" or \"suffix\"", |v| {
+                match string_value(v)?.as_str() {
+                    "suffix" => Some(MarkerPosition::Suffix),
+                    "prefix" => Some(MarkerPosition::Prefix),
+                    _ => None,
                 }
-            }
-            if !current_comment.is_empty() {
-                if current_table.ends_with("dependencies") {
-                    if !rest
-                        .split_once("optional")
-                        .and_then(|(_, r)| r.trim().strip_prefix('='))
-                        .map_or(false, |r| r.trim().starts_with("true"))
-                    {
-                        return Err(format!("Dependency {} is not an optional dependency", dep));
+            })?
+        }
+        "default_marker_emphasis" => {
+            args.default_marker_emphasis =
+                Some(parse_value(value, "\"italic\", \"bold\", \"code\" or \"none\"", |v| {
+                    match string_value(v)?.as_str() {
+                        "italic" => Some(Emphasis::Italic),
+                        "bold" => Some(Emphasis::Bold),
+                        "code" => Some(Emphasis::Code),
+                        "none" => Some(Emphasis::None),
+                        _ => None,
                     }
-                } else if current_table != "features" {
-                    return Err(format!(
-                        r#"Comment cannot be associated with a feature: "{}""#,
-                        current_comment.trim()
-                    ));
+                })?)
+        }
+        "default_label" => args.default_label = Some(parse_string(value)?),
+        "comment_position" => {
+            args.comment_position =
+                parse_value(value, "\"above\" or \"below\"", |v| match string_value(v)?.as_str() {
+                    "above" => Some(CommentPosition::Above),
+                    "below" => Some(CommentPosition::Below),
+                    _ => None,
+                })?
+        }
+        "join" => {
+            args.join = parse_value(value, "\"newline\" or \"space\"", |v| {
+                match string_value(v)?.as_str() {
+                    "newline" => Some(Join::Newline),
+                    "space" => Some(Join::Space),
+                    _ => None,
                 }
-                features.push((
-                    dep,
-                    std::mem::take(&mut top_comment),
-                    std::mem::take(&mut current_comment),
-                ));
-            }
+            })?
         }
+        _ => return Err(ArgError::Unknown),
     }
-    let df = default_features.iter().cloned().collect::<Vec<_>>();
-    for feature in df {
-        let mut resolved = HashSet::new();
-        dependents(&dependencies, &feature, &mut resolved);
-        default_features.extend(resolved.into_iter());
+    Ok(())
+}
+
+/// Report an error for the arguments that are set but not supported by the selected format,
+/// since they would otherwise be silently ignored
+fn check_format_support(args: &Args) -> Result<(), String> {
+    const MARKDOWN: &[Format] = &[Format::Markdown];
+    const HTML: &[Format] = &[Format::Html, Format::Dl];
+    // The formats that can link to the features
+    const LINKS: &[Format] = &[Format::Markdown, Format::Table, Format::Html, Format::Dl];
+    // The graph of `"mermaid"` has all the features of the `[features]` table
+    const FILTERED: &[Format] = &[
+        Format::Markdown,
+        Format::Badges,
+        Format::Tiers,
+        Format::Text,
+        Format::Html,
+        Format::Table,
+        Format::Dl,
+        Format::Rst,
+    ];
+    let supported: &[(&str, bool, &[Format])] = &[
+        (
+            "feature_label",
+            args.feature_label.is_some(),
+            &[Format::Markdown, Format::Table, Format::Tiers],
+        ),
+        (
+            "default_feature_label",
+            args.default_feature_label.is_some(),
+            &[Format::Markdown, Format::Table],
+        ),
+        ("join", args.join != Join::Newline, MARKDOWN),
+        ("max_description_chars", args.max_description_chars.is_some(), MARKDOWN),
+        ("normalize_case", args.normalize_case, MARKDOWN),
+        ("template", args.template.is_some(), MARKDOWN),
+        ("line_template", args.line_template.is_some(), MARKDOWN),
+        ("description_template", args.description_template.is_some(), MARKDOWN),
+        ("separator", args.separator.is_some(), MARKDOWN),
+        ("bullet", args.bullet.is_some(), MARKDOWN),
+        ("default_icon", args.default_icon.is_some(), MARKDOWN),
+        (
+            "default_marker_position",
+            args.default_marker_position == MarkerPosition::Prefix,
+            MARKDOWN,
+        ),
+        ("default_marker_emphasis", args.default_marker_emphasis.is_some(), MARKDOWN),
+        (
+            "default_label",
+            args.default_label.is_some(),
+            &[Format::Markdown, Format::Html, Format::Dl, Format::Rst],
+        ),
+        ("mark_dependencies", args.mark_dependencies, MARKDOWN),
+        ("annotate_target", args.annotate_target, MARKDOWN),
+        ("show_source", args.show_source, MARKDOWN),
+        ("group_separator", args.group_separator.is_some(), MARKDOWN),
+        ("indent_dependents", args.indent_dependents, MARKDOWN),
+        ("toc", args.toc, LINKS),
+        ("anchors", args.anchors, LINKS),
+        ("cross_link", args.cross_link, &[Format::Markdown, Format::Table]),
+        ("legend", args.legend, MARKDOWN),
+        ("data_attributes", args.data_attributes, HTML),
+        ("accessible", args.accessible, HTML),
+        ("wrapper_class", args.wrapper_class.is_some(), HTML),
+        ("profile", args.profile.is_some(), FILTERED),
+        ("prefix_filter", args.prefix_filter.is_some(), FILTERED),
+        ("include", args.include.is_some(), FILTERED),
+        ("exclude", args.exclude.is_some(), FILTERED),
+        ("badge_url", args.badge_url.is_some(), &[Format::Badges]),
+        ("badge_color", args.badge_color.is_some(), &[Format::Badges]),
+        ("badge_default_color", args.badge_default_color.is_some(), &[Format::Badges]),
+    ];
+    for (name, set, formats) in supported {
+        if *set && !formats.contains(&args.format) {
+            let mut names = formats.iter().map(|f| format!("\"{}\"", f.name())).collect::<Vec<_>>();
+            let last = names.pop().unwrap_or_default();
+            let names =
+                if names.is_empty() { last } else { format!("{} or {}", names.join(", "), last) };
+            return Err(format!("`{}` is only supported with format = {}", name, names));
+        }
     }
-    if !current_comment.is_empty() {
-        return Err("Found comment not associated with a feature".into());
+    Ok(())
+}
+
+/// The `key = value` pairs of the `[package.metadata.document-features]` table
+fn metadata_args(cargo_toml: &str) -> Result<Vec<(&str, Value)>, String> {
+    table_values(cargo_toml, "package.metadata.document-features")
+}
+
+/// The `key = value` pairs of the `[name]` table of Cargo.toml
+fn table_values<'a>(cargo_toml: &'a str, name: &str) -> Result<Vec<(&'a str, Value)>, String> {
+    let mut lines = cargo_toml.lines().map(str::trim);
+    let mut in_table = false;
+    let mut result = Vec::new();
+    while let Some(line) = lines.next() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(table) = line.strip_prefix('[') {
+            in_table = table.split_once(']').map(|(t, _)| t.trim()) == Some(name);
+        } else if in_table {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid line in [{}]: {}", name, line))?;
+            let value = get_balanced(value, &mut lines)?;
+            result.push((unquote_key(key.trim()), Value::from_toml(&value)));
+        }
     }
-    if features.is_empty() {
-        return Ok("*No documented features in Cargo.toml*".into());
+    Ok(result)
+}
+
+/// Set the arguments given in the `[package.metadata.document-features]` table of Cargo.toml
+fn apply_metadata(args: &mut Args, cargo_toml: &str) -> Result<(), String> {
+    for (key, value) in metadata_args(cargo_toml)? {
+        apply_arg(args, key, Some(&value)).map_err(|e| match e {
+            ArgError::Unknown => {
+                format!("Unknown argument `{}` in [package.metadata.document-features]", key)
+            }
+            ArgError::Invalid(msg) => {
+                format!("Invalid `{}` in [package.metadata.document-features]: {}", key, msg)
+            }
+        })?;
     }
-    let mut result = String::new();
-    for (f, top, comment) in features {
-        let default = if default_features.contains(f) { " *(enabled by default)*" } else { "" };
-        let feature_label = args.feature_label.as_deref().unwrap_or("**`{feature}`**");
-        let comment = if comment.trim().is_empty() {
-            String::new()
-        } else {
-            format!(" —{}", comment.trim_end())
-        };
+    Ok(())
+}
 
-        writeln!(
-            result,
-            "{}* {}{}{}",
-            top,
-            feature_label.replace("{feature}", f),
-            default,
-            comment,
-        )
-        .unwrap();
+/// Compute the arguments: the values from Cargo.toml are overridden by the arguments of the macro
+fn resolve_args(
+    cargo_toml: &str,
+    macro_args: Vec<(proc_macro::Ident, Option<TokenTree>)>,
+) -> Result<Args, TokenStream> {
+    let mut args = Args::default();
+    apply_metadata(&mut args, cargo_toml).map_err(|e| error(&e))?;
+    for (key, tt) in &macro_args {
+        apply_macro_arg(&mut args, key, tt)?;
     }
-    result += &top_comment;
-    Ok(result)
+    check_format_support(&args).map_err(|e| error(&e))?;
+    Ok(args)
 }
 
-fn get_balanced<'a>(
-    first_line: &'a str,
-    lines: &mut impl Iterator<Item = &'a str>,
-) -> Result<Cow<'a, str>, String> {
-    let mut line = first_line;
-    let mut result = Cow::from("");
+/// The `manifest_path` argument of the macro, which is needed to read Cargo.toml before
+/// resolving the other arguments
+fn manifest_path_arg(
+    macro_args: &[(proc_macro::Ident, Option<TokenTree>)],
+) -> Result<Option<String>, TokenStream> {
+    let mut args = Args::default();
+    for (key, tt) in macro_args.iter().filter(|(key, _)| key.to_string() == "manifest_path") {
+        apply_macro_arg(&mut args, key, tt)?;
+    }
+    Ok(args.manifest_path)
+}
 
-    let mut in_quote = false;
-    let mut level = 0;
-    loop {
-        let mut last_slash = false;
-        for (idx, b) in line.as_bytes().iter().enumerate() {
-            if last_slash {
-                last_slash = false
-            } else if in_quote {
-                match b {
-                    b'\\' => last_slash = true,
-                    b'"' | b'\'' => in_quote = false,
-                    _ => (),
+fn apply_macro_arg(
+    args: &mut Args,
+    key: &proc_macro::Ident,
+    tt: &Option<TokenTree>,
+) -> Result<(), TokenStream> {
+    let name = key.to_string();
+    let value = tt.as_ref().map(Value::from_token);
+    apply_arg(args, &name, value.as_ref()).map_err(|e| match e {
+        ArgError::Unknown => {
+            compile_error(&format!("unknown argument `{}`", name), Some(key.clone().into()))
+        }
+        ArgError::Invalid(msg) => compile_error(&msg, tt.clone()),
+    })
+}
+
+/// Produce a literal string containing documentation extracted from Cargo.toml
+///
+/// See the [crate] documentation for details
+#[proc_macro]
+pub fn document_features(tokens: TokenStream) -> TokenStream {
+    parse_args(tokens).and_then(document_features_impl).unwrap_or_else(std::convert::identity)
+}
+
+/// Produce an enum with one variant per documented feature
+///
+/// `document_features_enum!(Features)` expands to a `pub enum Features` with one variant per
+/// documented feature or optional dependency, named in CamelCase (`foo-bar` becomes `FooBar`, and
+/// `self` becomes `Self_`) and carrying the documentation of the feature.
+/// The generated enum has an `as_str()` function returning the name of the feature as written in
+/// Cargo.toml, an `ALL` constant with all the variants, and implements `Display`.
+///
+/// ```rust
+/// document_features::document_features_enum!(Features);
+///
+/// for feature in Features::ALL {
+///     println!("{}", feature);
+/// }
+/// ```
+#[proc_macro]
+pub fn document_features_enum(tokens: TokenStream) -> TokenStream {
+    document_features_enum_impl(tokens).unwrap_or_else(std::convert::identity)
+}
+
+fn document_features_enum_impl(tokens: TokenStream) -> Result<TokenStream, TokenStream> {
+    let mut token_trees = tokens.into_iter();
+    let name = match token_trees.next() {
+        Some(TokenTree::Ident(ident)) => ident,
+        tt => return Err(compile_error("expected the name of the enum", tt)),
+    };
+    if let tt @ Some(_) = token_trees.next() {
+        return Err(compile_error("unexpected token after the name of the enum", tt));
+    }
+    let cargo_toml = read_cargo_toml(None)?;
+    let parsed = parse_toml(&cargo_toml, &Args::default()).map_err(|e| error(&e))?;
+    let code = generate_enum(&name.to_string(), &parsed.features).map_err(|e| error(&e))?;
+    Ok(TokenStream::from_str(&code).unwrap())
+}
+
+/// Produce a module with one item per documented feature
+///
+/// `document_features_items!()` expands to a hidden `pub mod features` with one unit struct per
+/// documented feature or optional dependency, named in CamelCase like the variants of
+/// [`document_features_enum!`], and carrying the documentation of the feature.
+/// This allows tools that extract the documentation of items to get the documentation of each
+/// feature separately. Each struct has a `NAME` constant with the name of the feature.
+/// The name of the module can be given as argument: `document_features_items!(my_features)`.
+///
+/// ```rust
+/// document_features::document_features_items!();
+/// ```
+#[proc_macro]
+pub fn document_features_items(tokens: TokenStream) -> TokenStream {
+    document_features_items_impl(tokens).unwrap_or_else(std::convert::identity)
+}
+
+fn document_features_items_impl(tokens: TokenStream) -> Result<TokenStream, TokenStream> {
+    let mut token_trees = tokens.into_iter();
+    let module = match token_trees.next() {
+        None => "features".to_string(),
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        tt => return Err(compile_error("expected the name of the module", tt)),
+    };
+    if let tt @ Some(_) = token_trees.next() {
+        return Err(compile_error("unexpected token after the name of the module", tt));
+    }
+    let cargo_toml = read_cargo_toml(None)?;
+    let parsed = parse_toml(&cargo_toml, &Args::default()).map_err(|e| error(&e))?;
+    let code = generate_items(&module, &parsed.features).map_err(|e| error(&e))?;
+    Ok(TokenStream::from_str(&code).unwrap())
+}
+
+fn document_features_impl(
+    macro_args: Vec<(proc_macro::Ident, Option<TokenTree>)>,
+) -> Result<TokenStream, TokenStream> {
+    let manifest_path = manifest_file(manifest_path_arg(&macro_args)?.as_deref())?;
+    let cargo_toml = read_manifest(&manifest_path).map_err(|e| error(&e))?;
+    let args = resolve_args(&cargo_toml, macro_args)?;
+    let result = if args.workspace {
+        let root = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        process_workspace(root, &cargo_toml, &args)
+    } else {
+        process_toml(&cargo_toml, &args)
+    }
+    .map_err(|e| error(&e))?;
+    Ok(std::iter::once(proc_macro::TokenTree::from(proc_macro::Literal::string(&result))).collect())
+}
+
+/// Read the Cargo.toml of the crate being compiled
+fn read_cargo_toml(manifest_path: Option<&str>) -> Result<String, TokenStream> {
+    read_manifest(&manifest_file(manifest_path)?).map_err(|e| error(&e))
+}
+
+/// The path of the Cargo.toml of the crate being compiled, or of the manifest at `manifest_path`,
+/// relative to the directory of the crate
+fn manifest_file(manifest_path: Option<&str>) -> Result<PathBuf, TokenStream> {
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR")
+        .ok_or_else(|| error("CARGO_MANIFEST_DIR is not set, the crate must be built by cargo"))?;
+    Ok(Path::new(&dir).join(manifest_path.unwrap_or("Cargo.toml")))
+}
+
+/// Read the manifest at `path`, usually a Cargo.toml.
+/// This doesn't depend on the current directory, which is not the same for the macro and a build
+/// script, as long as `path` is absolute (like the ones in `CARGO_MANIFEST_DIR`).
+fn read_manifest(path: &Path) -> Result<String, String> {
+    let mut cargo_toml = std::fs::read_to_string(path)
+        .map_err(|e| format!("Can't open {}: {:?}", path.display(), e))?;
+    let manifest_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    if !has_doc_comments(&cargo_toml) {
+        // On crates.io, Cargo.toml is usually "normalized" and stripped of all comments.
+        // The original Cargo.toml has been renamed Cargo.toml.orig.
+        // Look for it next to the real Cargo.toml when the directory or the file is a symbolic
+        // link, and then next to the link.
+        let orig = |path: &Path| {
+            let mut name = path.file_name().unwrap_or_default().to_owned();
+            name.push(".orig");
+            path.with_file_name(name)
+        };
+        let mut candidates = vec![orig(path)];
+        if let Ok(real) = path.canonicalize() {
+            candidates.insert(0, orig(&real));
+        }
+        for candidate in candidates {
+            if let Ok(orig) = std::fs::read_to_string(candidate) {
+                if has_doc_comments(&orig) {
+                    cargo_toml = orig;
+                    break;
                 }
-            } else {
-                match b {
-                    b'\\' => last_slash = true,
-                    b'"' => in_quote = true,
-                    b'{' | b'[' => level += 1,
-                    b'}' | b']' if level == 0 => return Err("unbalanced source".into()),
-                    b'}' | b']' => level -= 1,
-                    b'#' => {
-                        line = &line[..idx];
+            }
+        }
+    }
+    include_features(&cargo_toml, manifest_dir)
+}
+
+/// Replace the `# @include-features: path` lines by the content of the file at `path`, relative
+/// to `manifest_dir`. The included files are not searched for other directives.
+fn include_features(cargo_toml: &str, manifest_dir: &Path) -> Result<String, String> {
+    let mut result = String::with_capacity(cargo_toml.len());
+    let mut string = None;
+    for line in cargo_toml.lines() {
+        let path = line
+            .trim()
+            .strip_prefix('#')
+            .filter(|_| string.is_none())
+            .and_then(|l| l.trim_start().strip_prefix("@include-features:").map(str::trim));
+        string = multi_line_string(line, string);
+        match path {
+            Some(path) => {
+                let path = manifest_dir.join(path);
+                let fragment = std::fs::read_to_string(&path).map_err(|e| {
+                    format!("Can't open the included file {}: {:?}", path.display(), e)
+                })?;
+                result += &fragment;
+                if !fragment.ends_with('\n') {
+                    result.push('\n');
+                }
+            }
+            None => {
+                result += line;
+                result.push('\n');
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// The delimiter of the multi-line string that is still open at the end of `line`, given the one
+/// that was open at its start.
+fn multi_line_string(line: &str, mut open: Option<&'static str>) -> Option<&'static str> {
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        match open {
+            Some(delimiter) if rest.starts_with(delimiter) => {
+                rest = rest.trim_start_matches(c);
+                open = None;
+                continue;
+            }
+            Some("\"\"\"") if c == '\\' => rest = &rest[1..],
+            Some(_) => (),
+            None if c == '#' => break,
+            None if rest.starts_with("\"\"\"") || rest.starts_with("'''") => {
+                open = Some(if c == '"' { "\"\"\"" } else { "'''" });
+                rest = &rest[3..];
+                continue;
+            }
+            None if c == '"' || c == '\'' => {
+                // A single-line string: skip to the closing quote
+                let mut chars = rest[1..].char_indices();
+                let mut end = rest.len();
+                while let Some((i, ch)) = chars.next() {
+                    if ch == c {
+                        end = i + 2;
                         break;
+                    } else if ch == '\\' && c == '"' {
+                        chars.next();
                     }
-                    _ => (),
                 }
+                rest = &rest[end..];
+                continue;
             }
+            None => (),
         }
-        if result.len() == 0 {
-            result = Cow::from(line);
-        } else {
-            *result.to_mut() += line;
+        rest = &rest[rest.chars().next().map_or(0, char::len_utf8)..];
+    }
+    open
+}
+
+/// The directories of the `members` of the `[workspace]` table, relative to the workspace root.
+/// A `*` as the last component of a member matches all the subdirectories with a Cargo.toml, and
+/// the directories in `exclude` are skipped.
+fn workspace_members(root: &Path, cargo_toml: &str) -> Result<Vec<PathBuf>, String> {
+    let mut members = None;
+    let mut exclude = Vec::new();
+    for (key, value) in table_values(cargo_toml, "workspace")? {
+        let paths = parse_list(Some(&value), "paths", |s| Some(s.to_string()))
+            .map_err(|_| format!("Invalid `{}` in [workspace]: expected an array of paths", key));
+        match key {
+            "members" => members = Some(paths?),
+            "exclude" => exclude = paths?,
+            _ => (),
         }
-        if level == 0 {
-            return Ok(result);
+    }
+    let members = members.ok_or("`workspace` requires a [workspace] table with `members`")?;
+    let exclude = exclude.iter().map(|e| root.join(e)).collect::<HashSet<_>>();
+    let mut result = Vec::new();
+    for member in members {
+        match member.strip_suffix('*') {
+            Some(parent) if parent.is_empty() || parent.ends_with('/') => {
+                let parent = root.join(parent);
+                let entries = std::fs::read_dir(&parent)
+                    .map_err(|e| format!("Can't read {}: {:?}", parent.display(), e))?;
+                let mut dirs = entries
+                    .filter_map(|e| Some(e.ok()?.path()))
+                    .filter(|p| p.join("Cargo.toml").is_file())
+                    .collect::<Vec<_>>();
+                dirs.sort();
+                result.extend(dirs);
+            }
+            _ => result.push(root.join(member)),
         }
-        line = if let Some(l) = lines.next() {
-            l
-        } else {
-            return Err("unbalanced source".into());
-        };
     }
+    result.retain(|dir| !exclude.contains(dir));
+    Ok(result)
 }
 
-#[test]
-fn test_get_balanced() {
-    assert_eq!(
-        get_balanced(
-            "{",
-            &mut IntoIterator::into_iter(["a", "{ abc[], #ignore", " def }", "}", "xxx"])
-        ),
-        Ok("{a{ abc[],  def }}".into())
-    );
-    assert_eq!(
-        get_balanced("{ foo = \"{#\" } #ignore", &mut IntoIterator::into_iter(["xxx"])),
-        Ok("{ foo = \"{#\" } ".into())
-    );
-    assert_eq!(
-        get_balanced("]", &mut IntoIterator::into_iter(["["])),
-        Err("unbalanced source".into())
-    );
+/// The `name` of the `[package]` table
+fn package_name(cargo_toml: &str) -> Option<String> {
+    let mut in_package = false;
+    for line in cargo_toml.lines().map(str::trim) {
+        if let Some(table) = line.strip_prefix('[') {
+            in_package = table.split_once(']').map(|(t, _)| t.trim()) == Some("package");
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_package) {
+            if unquote_key(key.trim()) == "name" {
+                return string_value(&Value::from_toml(value));
+            }
+        }
+    }
+    None
 }
 
-#[cfg(feature = "self-test")]
-#[proc_macro]
-#[doc(hidden)]
-/// Helper macro for the tests. Do not use
-pub fn self_test_helper(input: TokenStream) -> TokenStream {
-    let mut code = String::new();
-    for line in (&input).to_string().trim_matches(|c| c == '"' || c == '#').lines() {
-        // Rustdoc removes the lines that starts with `# ` and removes one `#` from lines that starts with # followed by space.
-        // We need to re-add the `#` that was removed by rustdoc to get the original.
-        if line.strip_prefix('#').map_or(false, |x| x.is_empty() || x.starts_with(' ')) {
-            code += "#";
+/// Document the features of all the members of the workspace whose root manifest is `cargo_toml`,
+/// with a heading with the name of each crate. The members without doc comments are skipped.
+fn process_workspace(root: &Path, cargo_toml: &str, args: &Args) -> Result<String, String> {
+    let mut result = String::new();
+    for dir in workspace_members(root, cargo_toml)? {
+        let member_toml = read_manifest(&dir.join("Cargo.toml"))?;
+        if !has_doc_comments(&member_toml) {
+            continue;
         }
-        code += line;
-        code += "\n";
+        let name = package_name(&member_toml).unwrap_or_else(|| {
+            dir.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned())
+        });
+        // The same feature can be in several crates, so the anchors need the name of the crate
+        let member_args = Args { anchor_prefix: Some(name.clone()), ..args.clone() };
+        let features = process_toml(&member_toml, &member_args)
+            .map_err(|e| format!("In crate `{}`: {}", name, e))?;
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        result += &match args.format {
+            Format::Html | Format::Dl => format!("<h3><code>{}</code></h3>\n", html_escape(&name)),
+            Format::Rst => format!("``{0}``\n{1}\n\n", name, "-".repeat(name.len() + 4)),
+            Format::Text => format!("{}\n\n", name),
+            _ => format!("### `{}`\n\n", name),
+        };
+        result += &features;
+    }
+    if result.is_empty() {
+        return Err("Could not find documented features in the members of the workspace".into());
+    }
+    Ok(result)
+}
+
+/// Check if the Cargo.toml has comments that looks like doc comments.
+fn has_doc_comments(cargo_toml: &str) -> bool {
+    let mut lines = cargo_toml.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if line.starts_with("## ") || line.starts_with("#! ") {
+            return true;
+        }
+        let before_coment = line.split_once('#').map_or(line, |(before, _)| before);
+        if line.starts_with("#") {
+            continue;
+        }
+        if let Some((_, mut quote)) = before_coment.split_once("\"\"\"") {
+            loop {
+                // skip slashes.
+                if let Some((_, s)) = quote.split_once('\\') {
+                    quote = s.strip_prefix('\\').or_else(|| s.strip_prefix('"')).unwrap_or(s);
+                    continue;
+                }
+                // skip quotes.
+                if let Some((_, out_quote)) = quote.split_once("\"\"\"") {
+                    let out_quote = out_quote.trim_start_matches('"');
+                    let out_quote =
+                        out_quote.split_once('#').map_or(out_quote, |(before, _)| before);
+                    if let Some((_, q)) = out_quote.split_once("\"\"\"") {
+                        quote = q;
+                        continue;
+                    }
+                    break;
+                };
+                match lines.next() {
+                    Some(l) => quote = l,
+                    None => return false,
+                }
+            }
+        }
+    }
+    false
+}
+
+#[test]
+fn test_has_doc_coment() {
+    assert!(has_doc_comments("foo\nbar\n## comment\nddd"));
+    assert!(!has_doc_comments("foo\nbar\n#comment\nddd"));
+    assert!(!has_doc_comments(
+        r#"
+[[package.metadata.release.pre-release-replacements]]
+exactly = 1 # not a doc comment
+file = "CHANGELOG.md"
+replace = """
+<!-- next-header -->
+## [Unreleased] - ReleaseDate
+"""
+search = "<!-- next-header -->"
+array = ["""foo""", """
+bar""", """eee
+## not a comment
+"""]
+    "#
+    ));
+    assert!(has_doc_comments(
+        r#"
+[[package.metadata.release.pre-release-replacements]]
+exactly = 1 # """
+file = "CHANGELOG.md"
+replace = """
+<!-- next-header -->
+## [Unreleased] - ReleaseDate
+"""
+search = "<!-- next-header -->"
+array = ["""foo""", """
+bar""", """eee
+## not a comment
+"""]
+## This is a comment
+feature = "45"
+        "#
+    ));
+
+    assert!(!has_doc_comments(
+        r#"
+[[package.metadata.release.pre-release-replacements]]
+value = """" string \"""
+## within the string
+\""""
+another_string = """"" # """
+## also within"""
+"#
+    ));
+
+    assert!(has_doc_comments(
+        r#"
+[[package.metadata.release.pre-release-replacements]]
+value = """" string \"""
+## within the string
+\""""
+another_string = """"" # """
+## also within"""
+## out of the string
+foo = bar
+        "#
+    ));
+}
+
+/// Add to `collected` the `feature` and all the features it enables, transitively.
+/// This is iterative and each feature is only visited once, even for very long chains.
+fn dependents(
+    feature_dependencies: &HashMap<String, Vec<String>>,
+    feature: &str,
+    collected: &mut HashSet<String>,
+) {
+    let mut stack = vec![feature];
+    while let Some(feature) = stack.pop() {
+        if !collected.insert(feature.to_string()) {
+            continue;
+        }
+        if let Some(dependencies) = feature_dependencies.get(feature) {
+            stack.extend(dependencies.iter().map(String::as_str));
+        }
+    }
+}
+
+fn parse_feature_deps<'a>(
+    s: &'a str,
+    dep: &str,
+) -> Result<impl Iterator<Item = String> + 'a, String> {
+    Ok(s.trim()
+        .strip_prefix('[')
+        .and_then(|r| r.strip_suffix(']'))
+        .ok_or_else(|| format!("Parse error while parsing dependency {}", dep))?
+        .split(',')
+        .map(|d| d.trim().trim_matches(|c| c == '"' || c == '\'').trim().to_string())
+        .filter(|d: &String| !d.is_empty()))
+}
+
+/// A documented feature or optional dependency
+struct Feature<'a> {
+    name: &'a str,
+    /// The `#!` comments that come before this feature
+    top: String,
+    /// The `##` comments documenting this feature, without the tags
+    comment: String,
+    /// The tags (`## @name: value` lines) found in the comment
+    tags: Vec<(&'static str, String)>,
+    /// The lines of Cargo.toml declaring this feature
+    source: &'a str,
+    /// Whether this is an optional dependency rather than a feature of the `[features]` table
+    dependency: bool,
+    /// The targets of the `[target.<cfg>.dependencies]` tables declaring this optional dependency,
+    /// or empty if it is declared for all the targets
+    targets: Vec<&'a str>,
+    /// Whether this optional dependency is only declared in `[dev-dependencies]` tables
+    dev_dependency: bool,
+    /// Whether this feature has no `## ` comment and is only listed with `include_undocumented`
+    undocumented: bool,
+}
+
+/// The names of the tags that can be used in the `##` comments
+const TAGS: &[&str] = &[
+    "stability",
+    "alternative-to",
+    "icon",
+    "requires",
+    "platform",
+    "msrv",
+    "enables-item",
+    "since",
+    "non-additive",
+    "default-on",
+];
+
+/// The values of the `@stability` tag, in the order used by `sort = "stability"`.
+/// Features without the tag are stable.
+const STABILITY_LEVELS: &[&str] = &["stable", "experimental", "deprecated"];
+
+impl<'a> Feature<'a> {
+    /// Create a feature, extracting the tags from its comment
+    fn new(name: &'a str, top: String, comment: String) -> Result<Self, String> {
+        let mut tags = Vec::new();
+        let mut description = String::with_capacity(comment.len());
+        for line in comment.lines() {
+            let tag = line.trim().strip_prefix('@').and_then(|t| {
+                let (tag, value) = t.split_once(':').unwrap_or((t, ""));
+                TAGS.iter().find(|n| **n == tag.trim()).map(|n| (*n, value.trim().to_string()))
+            });
+            match tag {
+                Some(tag) => tags.push(tag),
+                None => {
+                    description += line;
+                    description.push('\n');
+                }
+            }
+        }
+        let feature = Feature {
+            name,
+            top,
+            comment: description,
+            tags,
+            source: "",
+            dependency: false,
+            targets: Vec::new(),
+            dev_dependency: false,
+            undocumented: false,
+        };
+        if feature.tags.iter().filter(|(n, _)| *n == "icon").count() > 1 {
+            return Err(format!("Feature {} has more than one @icon tag", name));
+        }
+        if let Some(stability) = feature.tag("stability") {
+            if !STABILITY_LEVELS.contains(&stability) {
+                return Err(format!(
+                    "Unknown stability `{}` for feature {}, expected one of: {}",
+                    stability,
+                    name,
+                    STABILITY_LEVELS.join(", ")
+                ));
+            }
+        }
+        Ok(feature)
+    }
+
+    /// Create a feature without documentation, for `include_undocumented`, with the `placeholder`
+    /// as description
+    fn undocumented(name: &'a str, top: String, placeholder: &str) -> Result<Self, String> {
+        let comment =
+            if placeholder.is_empty() { String::new() } else { format!("  {}\n", placeholder) };
+        let mut feature = Feature::new(name, top, comment)?;
+        feature.undocumented = true;
+        Ok(feature)
+    }
+
+    /// The value of the tag `name`, if this feature has it
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn stability(&self) -> &str {
+        self.tag("stability").unwrap_or("stable")
+    }
+}
+
+/// The documentation extracted from Cargo.toml
+struct ParsedToml<'a> {
+    features: Vec<Feature<'a>>,
+    /// The features enabled by default, including the ones enabled by other default features
+    default_features: HashSet<String>,
+    /// The `#!` comments after the last feature
+    top_comment: String,
+    /// The profiles from `[package.metadata.document-features.profiles]`
+    profiles: HashMap<String, Vec<String>>,
+    /// The tiers from `[package.metadata.document-features.tiers]`, in the order of declaration
+    tiers: Vec<(String, Vec<String>)>,
+    /// All the features of the `[features]` table (including `default`) in the order of
+    /// declaration, with the features they enable
+    enables: Vec<(&'a str, Vec<String>)>,
+    /// The declared dependencies, and whether they are optional in one of their declarations
+    optional_deps: HashMap<&'a str, bool>,
+}
+
+/// Returns true if the value of a dependency has `optional = true`.
+/// The `optional` key must follow the `{` or a `,` of the inline table, so that a feature such as
+/// `features = ["optional-foo"]` is not taken for the key.
+fn is_optional(value: &str) -> bool {
+    value.match_indices("optional").any(|(idx, key)| {
+        value[..idx].trim_end().ends_with(|c| c == '{' || c == ',')
+            && value[idx + key.len()..]
+                .trim_start()
+                .strip_prefix('=')
+                .map_or(false, |r| r.trim_start().starts_with("true"))
+    })
+}
+
+/// With `comment_position = "below"`, add the `previous` feature if it has a comment, or if
+/// `undocumented` has the placeholder of `include_undocumented`.
+/// `previous` is the name of the feature, whether it can be documented, and whether it is a
+/// dependency.
+fn push_comment_below<'a>(
+    previous: Option<(&'a str, bool, bool)>,
+    features: &mut Vec<Feature<'a>>,
+    top_comment: &mut String,
+    current_comment: &mut String,
+    undocumented: Option<&str>,
+) -> Result<(), String> {
+    if let Some((name, can_be_documented, dependency)) = previous {
+        if !current_comment.is_empty() {
+            if !can_be_documented {
+                return Err(format!("Dependency {} is not an optional dependency", name));
+            }
+            let mut feature =
+                Feature::new(name, std::mem::take(top_comment), std::mem::take(current_comment))?;
+            feature.dependency = dependency;
+            features.push(feature);
+        } else if let Some(placeholder) = undocumented.filter(|_| !dependency && name != "default")
+        {
+            features.push(Feature::undocumented(name, std::mem::take(top_comment), placeholder)?);
+        }
+    }
+    Ok(())
+}
+
+/// The part of `source` from the start of `first` to the end of `last`, which are sub-slices of it
+fn span<'a>(source: &'a str, first: &str, last: &str) -> &'a str {
+    let start = first.as_ptr() as usize - source.as_ptr() as usize;
+    let end = last.as_ptr() as usize + last.len() - source.as_ptr() as usize;
+    &source[start..end]
+}
+
+/// Remove the quotes of a quoted key such as `"1"` or `'true'`, which are regular feature names.
+/// Stray whitespace and control characters, such as a `\r`, are removed too so that they don't
+/// end up in the output.
+fn unquote_key(key: &str) -> &str {
+    let mut key = key.trim();
+    for quote in &['"', '\''] {
+        if let Some(k) = key.strip_prefix(*quote).and_then(|k| k.strip_suffix(*quote)) {
+            key = k;
+            break;
+        }
+    }
+    key.trim_matches(|c: char| c.is_whitespace() || c.is_control())
+}
+
+/// The `<cfg>` of a `target.<cfg>.dependencies` table, or of its `build-dependencies` and
+/// `dev-dependencies` variants, without the quotes
+fn target_of(table: &str) -> Option<&str> {
+    let (cfg, _) = table.strip_prefix("target.")?.rsplit_once("dependencies")?;
+    let cfg = cfg.strip_suffix("build-").or_else(|| cfg.strip_suffix("dev-")).unwrap_or(cfg);
+    Some(cfg.strip_suffix('.')?.trim().trim_matches(|c| c == '\'' || c == '"'))
+}
+
+fn parse_toml<'a>(cargo_toml: &'a str, args: &Args) -> Result<ParsedToml<'a>, String> {
+    let below = args.comment_position == CommentPosition::Below;
+    // The last line returned by `lines`
+    let last_line = Cell::new("");
+    // All the lines, including the ones that `get_balanced` needs to see to find the end of
+    // multi-line strings
+    let mut lines = cargo_toml.lines().map(str::trim).inspect(|l| last_line.set(l));
+    // The lines declaring each feature or dependency
+    let mut sources = HashMap::new();
+    // The dependency and the header of the current `[dependencies.foo]` table, when it is the
+    // first declaration of that dependency, so that its keys are added to its source
+    let mut table_source = None;
+    // The target of each declaration of a dependency, `None` if it is not target specific
+    let mut targets = HashMap::new();
+    // Whether each declaration of a dependency is in a `dev-dependencies` table
+    let mut dev_declarations = HashMap::new();
+    let mut optional_deps = HashMap::new();
+    let mut top_comment = String::new();
+    let mut current_comment = String::new();
+    let mut features = vec![];
+    let mut default_features = Vec::new();
+    let mut current_table = "";
+    let mut dependencies = HashMap::new();
+    let mut profiles = HashMap::new();
+    let mut tiers = Vec::new();
+    let mut enables = Vec::new();
+    // The last feature, when the comments are below the features
+    let mut previous = None;
+    // A comment that is not a doc comment, found after the `## ` comments in `current_comment`
+    let mut interruption = None;
+    while let Some(line) = lines.next() {
+        // Skip empty lines and comments that are not docs comments, unless they need to be
+        // checked with `strict_comment_blocks`
+        if line.is_empty()
+            || (line.starts_with('#')
+                && !line.starts_with("##")
+                && !line.starts_with("#!")
+                && !args.strict_comment_blocks)
+        {
+            continue;
+        }
+        // `###` or `#!foo` are not doc comments, but they still interrupt a block of `## ` comments
+        let not_doc = |x: &str| !x.is_empty() && !x.starts_with(' ');
+        if line.strip_prefix("##").or_else(|| line.strip_prefix("#!")).map_or(false, not_doc) {
+            if args.strict_comment_blocks && !current_comment.is_empty() {
+                interruption = Some(line);
+            }
+            continue;
+        }
+        if let Some(x) = line.strip_prefix("#!") {
+            if below {
+                push_comment_below(
+                    previous.take(),
+                    &mut features,
+                    &mut top_comment,
+                    &mut current_comment,
+                    args.include_undocumented.as_deref(),
+                )?;
+            } else if !current_comment.is_empty() {
+                return Err("A feature doc comment (##) was immediately followed by a group comment \
+                            (#!) before any feature; move the group comment or add the feature first."
+                    .into());
+            }
+            if top_comment.is_empty() && !features.is_empty() {
+                top_comment = "\n".into();
+            }
+            writeln!(top_comment, "{}", x).unwrap();
+        } else if let Some(x) = line.strip_prefix("##") {
+            if below && previous.is_none() {
+                return Err(format!(
+                    r#"Comment cannot be associated with a feature: "{}""#,
+                    x.trim()
+                ));
+            }
+            if let Some(interruption) = interruption.take() {
+                if !current_comment.is_empty() {
+                    return Err(format!(
+                        r#"The comment "{}" interrupts the ## comments: "{}""#,
+                        interruption,
+                        current_comment.trim()
+                    ));
+                }
+            }
+            writeln!(current_comment, " {}", x).unwrap();
+        } else if line.starts_with('#') {
+            // Only not skipped with `strict_comment_blocks`
+            if !current_comment.is_empty() {
+                interruption = Some(line);
+            }
+        } else if let Some(table) = line.strip_prefix('[') {
+            current_table = table
+                .split_once(']')
+                .map(|(t, _)| t.trim())
+                .ok_or_else(|| format!("Parse error while parsing line: {}", line))?;
+            table_source = None;
+            if let Some((table, dep)) = current_table.rsplit_once('.') {
+                if table.trim().ends_with("dependencies") {
+                    if !sources.contains_key(dep.trim()) {
+                        sources.insert(dep.trim(), line);
+                        table_source = Some((dep.trim(), line));
+                    }
+                    targets.entry(dep.trim()).or_insert_with(Vec::new).push(target_of(table));
+                    dev_declarations
+                        .entry(dep.trim())
+                        .or_insert_with(Vec::new)
+                        .push(table.trim().ends_with("dev-dependencies"));
+                    // `[workspace.dependencies]` are only inherited, not dependencies of the crate
+                    if !table.trim().starts_with("workspace.") {
+                        optional_deps.entry(dep.trim()).or_insert(false);
+                    }
+                }
+            }
+            if below {
+                push_comment_below(
+                    previous.take(),
+                    &mut features,
+                    &mut top_comment,
+                    &mut current_comment,
+                    args.include_undocumented.as_deref(),
+                )?;
+                previous = current_table
+                    .rsplit_once('.')
+                    .filter(|(table, _)| table.trim().ends_with("dependencies"))
+                    .map(|(_, dep)| (dep.trim(), true, true));
+            } else if !current_comment.is_empty()
+                && args.section_intro
+                && (current_table == "features" || current_table.ends_with("dependencies"))
+            {
+                // The comment introduces the section, like a `#!` comment
+                if top_comment.is_empty() && !features.is_empty() {
+                    top_comment = "\n".into();
+                }
+                for l in std::mem::take(&mut current_comment).lines() {
+                    writeln!(top_comment, "{}", l.strip_prefix(' ').unwrap_or(l)).unwrap();
+                }
+            } else if !current_comment.is_empty() {
+                #[allow(clippy::unnecessary_lazy_evaluations)]
+                let dep = current_table
+                    .rsplit_once('.')
+                    .and_then(|(table, dep)| table.trim().ends_with("dependencies").then(|| dep))
+                    .ok_or_else(|| format!("Not a feature: `{}`", line))?;
+                let mut feature = Feature::new(
+                    dep.trim(),
+                    std::mem::take(&mut top_comment),
+                    std::mem::take(&mut current_comment),
+                )?;
+                feature.dependency = true;
+                features.push(feature);
+            }
+        } else if let Some((dep, rest)) = line.split_once('=') {
+            let dep = unquote_key(dep.trim());
+            let rest = get_balanced(rest, &mut lines)
+                .map_err(|e| format!("Parse error while parsing value {}: {}", dep, e))?;
+            if current_table == "features" {
+                let deps = parse_feature_deps(&rest, dep)?.collect::<Vec<_>>();
+                if dep == "default" {
+                    if enables.iter().any(|(f, _)| *f == "default") {
+                        // Cargo rejects duplicate keys, but hand-edited files may have them
+                        if !args.last_default {
+                            return Err("The `default` feature is defined more than once".into());
+                        }
+                        default_features.clear();
+                        enables.retain(|(f, _)| *f != "default");
+                    }
+                    default_features.extend(deps.iter().cloned());
+                } else {
+                    dependencies
+                        .entry(dep.to_string())
+                        .or_insert_with(Vec::new)
+                        .extend(deps.iter().cloned());
+                }
+                enables.push((dep, deps));
+            } else if current_table == "package.metadata.document-features.tiers" {
+                tiers.push((dep.to_string(), parse_feature_deps(&rest, dep)?.collect()));
+            } else if current_table == "package.metadata.document-features.profiles" {
+                profiles.insert(dep.to_string(), parse_feature_deps(&rest, dep)?.collect());
+            } else if let Some(profile) =
+                current_table.strip_prefix("package.metadata.document-features.profiles.")
+            {
+                if dep == "features" {
+                    profiles.insert(profile.to_string(), parse_feature_deps(&rest, dep)?.collect());
+                }
+            }
+            let in_dependency_table = current_table
+                .rsplit_once('.')
+                .map_or(false, |(table, _)| table.trim().ends_with("dependencies"));
+            if current_table == "features"
+                || (current_table.ends_with("dependencies") && !in_dependency_table)
+            {
+                sources.entry(dep).or_insert_with(|| span(cargo_toml, line, last_line.get()));
+            } else if in_dependency_table {
+                // The keys of a `[dependencies.foo]` table are part of the declaration
+                if let Some((table_dep, header)) = table_source {
+                    sources.insert(table_dep, span(cargo_toml, header, last_line.get()));
+                }
+            }
+            if current_table.ends_with("dependencies") && !in_dependency_table {
+                targets.entry(dep).or_insert_with(Vec::new).push(target_of(current_table));
+                dev_declarations
+                    .entry(dep)
+                    .or_insert_with(Vec::new)
+                    .push(current_table.ends_with("dev-dependencies"));
+                if !current_table.starts_with("workspace.") {
+                    *optional_deps.entry(dep).or_insert(false) |= is_optional(&rest);
+                }
+            } else if in_dependency_table && dep == "optional" && rest.trim() == "true" {
+                match current_table.rsplit_once('.') {
+                    Some((table, _)) if table.starts_with("workspace.") => (),
+                    Some((_, table_dep)) => {
+                        optional_deps.insert(table_dep.trim(), true);
+                    }
+                    None => (),
+                }
+            }
+            if below {
+                // The keys within a `[dependencies.foo]` table don't change the documented feature
+                if !in_dependency_table {
+                    push_comment_below(
+                        previous.take(),
+                        &mut features,
+                        &mut top_comment,
+                        &mut current_comment,
+                        args.include_undocumented.as_deref(),
+                    )?;
+                    if current_table == "features" {
+                        previous = Some((dep, true, false));
+                    } else if current_table.ends_with("dependencies") {
+                        previous = Some((dep, is_optional(&rest), true));
+                    }
+                }
+            } else if !current_comment.is_empty() {
+                if current_table.ends_with("dependencies") {
+                    if !is_optional(&rest) {
+                        return Err(format!("Dependency {} is not an optional dependency", dep));
+                    }
+                } else if current_table != "features" {
+                    return Err(format!(
+                        r#"Comment cannot be associated with a feature: "{}""#,
+                        current_comment.trim()
+                    ));
+                }
+                let mut feature = Feature::new(
+                    dep,
+                    std::mem::take(&mut top_comment),
+                    std::mem::take(&mut current_comment),
+                )?;
+                feature.dependency = current_table != "features";
+                features.push(feature);
+            } else if let Some(placeholder) = &args.include_undocumented {
+                if current_table == "features" && dep != "default" {
+                    let top = std::mem::take(&mut top_comment);
+                    features.push(Feature::undocumented(dep, top, placeholder)?);
+                }
+            }
+        }
+    }
+    push_comment_below(
+        previous,
+        &mut features,
+        &mut top_comment,
+        &mut current_comment,
+        args.include_undocumented.as_deref(),
+    )?;
+    for f in &mut features {
+        f.source = sources.get(f.name).copied().unwrap_or_default();
+        if let Some(declarations) = targets.get(f.name) {
+            if f.dependency && declarations.iter().all(Option::is_some) {
+                for target in declarations.iter().flatten() {
+                    if !f.targets.contains(target) {
+                        f.targets.push(target);
+                    }
+                }
+            }
+        }
+        if let Some(declarations) = dev_declarations.get(f.name) {
+            f.dev_dependency = f.dependency && declarations.iter().all(|dev| *dev);
+        }
+    }
+    // A single set for all the default features so that shared dependencies are visited once
+    let mut resolved = HashSet::with_capacity(default_features.len());
+    for feature in &default_features {
+        dependents(&dependencies, feature, &mut resolved);
+    }
+    let default_features = resolved;
+    if !current_comment.is_empty() {
+        return Err("Found comment not associated with a feature".into());
+    }
+    Ok(ParsedToml {
+        features,
+        default_features,
+        top_comment,
+        profiles,
+        tiers,
+        enables,
+        optional_deps,
+    })
+}
+
+/// Ensure that the `default` feature only references features or documented optional dependencies
+fn check_default_features(
+    enables: &[(&str, Vec<String>)],
+    features: &[Feature],
+) -> Result<(), String> {
+    let known = enables
+        .iter()
+        .map(|(f, _)| *f)
+        .filter(|f| *f != "default")
+        .chain(features.iter().map(|f| f.name))
+        .collect::<HashSet<_>>();
+    let defaults = enables.iter().filter(|(name, _)| *name == "default");
+    for name in defaults.flat_map(|(_, deps)| deps) {
+        // Features of dependencies are not checked
+        if name.contains('/') {
+            continue;
+        }
+        let name = name.strip_prefix("dep:").unwrap_or(name);
+        if !known.contains(name) {
+            return Err(format!(
+                "The `default` feature references `{}`, which is neither a feature nor a documented optional dependency",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Ensure that all the features of the `default` feature are documented
+fn check_default_docs(enables: &[(&str, Vec<String>)], features: &[Feature]) -> Result<(), String> {
+    let documented =
+        features.iter().filter(|f| !f.undocumented).map(|f| f.name).collect::<HashSet<_>>();
+    let mut undocumented = Vec::new();
+    let defaults = enables.iter().filter(|(name, _)| *name == "default");
+    for name in defaults.flat_map(|(_, deps)| deps) {
+        // Features of dependencies are documented by the dependencies
+        if name.contains('/') {
+            continue;
+        }
+        let name = name.strip_prefix("dep:").unwrap_or(name);
+        if !documented.contains(name) && !undocumented.contains(&name) {
+            undocumented.push(name);
+        }
+    }
+    if undocumented.is_empty() {
+        Ok(())
+    } else {
+        let undocumented = undocumented.iter().map(|f| format!("`{}`", f)).collect::<Vec<_>>();
+        Err(format!("Default features without documentation: {}", undocumented.join(", ")))
+    }
+}
+
+/// Ensure that the `dep:` references of the features are optional dependencies
+fn check_dep_refs(
+    enables: &[(&str, Vec<String>)],
+    optional_deps: &HashMap<&str, bool>,
+) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for (feature, deps) in enables {
+        for dep in deps.iter().filter_map(|d| d.strip_prefix("dep:")) {
+            match optional_deps.get(dep) {
+                Some(true) => (),
+                Some(false) => errors.push(format!(
+                    "Feature `{}` enables `dep:{}`, but this dependency is not optional",
+                    feature, dep
+                )),
+                None => errors.push(format!(
+                    "Feature `{}` enables `dep:{}`, but there is no such dependency",
+                    feature, dep
+                )),
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Report an error with the features of the `[features]` table that are not documented
+fn check_all_documented(
+    enables: &[(&str, Vec<String>)],
+    features: &[Feature],
+) -> Result<(), String> {
+    let documented =
+        features.iter().filter(|f| !f.undocumented).map(|f| f.name).collect::<HashSet<_>>();
+    let undocumented = enables
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| *name != "default" && !documented.contains(name))
+        .map(|name| format!("`{}`", name))
+        .collect::<Vec<_>>();
+    if undocumented.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Features without documentation: {}", undocumented.join(", ")))
+    }
+}
+
+/// Ensure that the descriptions have between `min` and `max` words
+fn check_description_words(
+    features: &[Feature],
+    min: Option<usize>,
+    max: Option<usize>,
+) -> Result<(), String> {
+    let words = |f: &&Feature| f.comment.split_whitespace().count();
+    // The placeholders of `include_undocumented` are not descriptions
+    let features = features.iter().filter(|f| !f.undocumented).collect::<Vec<_>>();
+    let list = |features: Vec<&Feature>| {
+        let list = features.iter().map(|f| format!("`{}` ({})", f.name, words(f)));
+        list.collect::<Vec<_>>().join(", ")
+    };
+    if let Some(min) = min {
+        let short = features.iter().copied().filter(|f| words(f) < min).collect::<Vec<_>>();
+        if !short.is_empty() {
+            return Err(format!("Descriptions with fewer than {} words: {}", min, list(short)));
+        }
+    }
+    if let Some(max) = max {
+        let long = features.iter().copied().filter(|f| words(f) > max).collect::<Vec<_>>();
+        if !long.is_empty() {
+            return Err(format!("Descriptions with more than {} words: {}", max, list(long)));
+        }
+    }
+    Ok(())
+}
+
+fn process_toml(cargo_toml: &str, args: &Args) -> Result<String, String> {
+    let ParsedToml {
+        mut features,
+        default_features,
+        mut top_comment,
+        profiles,
+        tiers,
+        enables,
+        optional_deps,
+    } = parse_toml(cargo_toml, args)?;
+    if args.warn_unknown_defaults {
+        check_default_features(&enables, &features)?;
+    }
+    if args.require_default_docs {
+        check_default_docs(&enables, &features)?;
+    }
+    if args.validate_dep_refs {
+        check_dep_refs(&enables, &optional_deps)?;
+    }
+    if args.require_all_documented {
+        check_all_documented(&enables, &features)?;
+    }
+    if args.format == Format::Mermaid {
+        return Ok(render_mermaid(&enables));
+    }
+    let lang = args.lang.as_deref().unwrap_or("en");
+    for f in &mut features {
+        f.comment = localize(&f.comment, lang);
+        f.top = localize(&f.top, lang);
+    }
+    top_comment = localize(&top_comment, lang);
+    check_description_words(&features, args.min_description_words, args.max_description_words)?;
+    if let Some(profile) = &args.profile {
+        let members = profiles.get(profile).ok_or_else(|| {
+            let mut known = profiles.keys().map(String::as_str).collect::<Vec<_>>();
+            known.sort_unstable();
+            format!("Unknown profile `{}`, expected one of: {}", profile, known.join(", "))
+        })?;
+        let members = members.iter().map(String::as_str).collect::<HashSet<_>>();
+        features = filter_features(features, |f| members.contains(f.name));
+    }
+    if let Some(prefix) = &args.prefix_filter {
+        features = filter_features(features, |f| f.name.starts_with(prefix.as_str()));
+        if features.is_empty() {
+            return Err(format!("No documented features start with `{}`", prefix));
+        }
+    }
+    if let Some(patterns) = &args.include {
+        features = filter_features(features, |f| patterns.iter().any(|p| glob_match(p, f.name)));
+        if features.is_empty() {
+            return Err(format!("No documented features match `{}`", patterns.join(", ")));
+        }
+    }
+    if let Some(patterns) = &args.exclude {
+        features = filter_features(features, |f| !patterns.iter().any(|p| glob_match(p, f.name)));
+        if features.is_empty() {
+            return Err(format!(
+                "All the documented features are excluded by `{}`",
+                patterns.join(", ")
+            ));
+        }
+    }
+    if features.is_empty() {
+        return Ok("*No documented features in Cargo.toml*".into());
+    }
+    if args.format == Format::Tiers {
+        return render_tiers(&features, &tiers, &enables, args);
+    }
+    match args.sort {
+        Sort::Declaration => (),
+        Sort::Stability => {
+            features = sort_by_stability(features);
+            top_comment.clear();
+        }
+        Sort::Alphabetical => features = sort_alphabetically(features),
+    }
+    if args.group_non_additive {
+        let (mut non_additive, mut others): (Vec<_>, Vec<_>) =
+            features.into_iter().partition(|f| f.tag("non-additive").is_some());
+        if let Some(first) = non_additive.first_mut() {
+            first.top = format!("\n#### Non-additive features (use with care)\n{}", first.top);
+        }
+        others.append(&mut non_additive);
+        features = others;
+    }
+    if args.document_dev_deps {
+        let (mut dev, mut others): (Vec<_>, Vec<_>) =
+            features.into_iter().partition(|f| f.dev_dependency);
+        if let Some(first) = dev.first_mut() {
+            first.top = format!("\n#### Development dependencies\n{}", first.top);
+        }
+        others.append(&mut dev);
+        features = others;
+    }
+    if args.format == Format::Badges {
+        return Ok(render_badges(features.iter().map(|f| f.name), &default_features, args));
+    }
+    let renderer: Box<dyn Renderer> = match args.format {
+        Format::Html => Box::new(Html { args, definitions: false }),
+        Format::Dl => Box::new(Html { args, definitions: true }),
+        Format::Rst => Box::new(Rst { args }),
+        Format::Text => Box::new(Text { args }),
+        Format::Table => Box::new(Table { args }),
+        _ => Box::new(Markdown { args }),
+    };
+    let mut summary = String::new();
+    if args.requirements_summary {
+        let requirements = requirements(&features);
+        if !requirements.is_empty() {
+            let text = format!("Some features require: {}.", requirements.join(", "));
+            summary = renderer.note(&text, false);
+        }
+    }
+    let mut default_note = String::new();
+    if args.no_default_note {
+        // Only keep the features, not the features of dependencies
+        let disabled = default_features
+            .iter()
+            .filter(|f| !f.contains('/'))
+            .map(|f| format!("`{}`", f.strip_prefix("dep:").unwrap_or(f)))
+            .collect::<BTreeSet<_>>();
+        if !disabled.is_empty() {
+            let disabled = disabled.into_iter().collect::<Vec<_>>().join(", ");
+            let text = format!("Disabling default features turns off: {}.", disabled);
+            default_note = renderer.note(&text, false);
+        }
+    }
+    if args.note_no_defaults && default_features.is_empty() {
+        default_note = renderer.note("No features are enabled by default.", true);
+    }
+    let mut levels = vec![0; features.len()];
+    if args.indent_dependents {
+        let (sorted, sorted_levels) = nest_dependents(features, &enables);
+        features = sorted;
+        levels = sorted_levels;
+    }
+    let mut legend_note = String::new();
+    if args.legend {
+        let text = legend(&features, &default_features, args);
+        if !text.is_empty() {
+            legend_note = renderer.note(&text, false);
+        }
+    }
+    let mut list = String::with_capacity(cargo_toml.len());
+    if let Some(note) = &args.implicit_features_note {
+        if features.iter().all(|f| f.dependency) {
+            list += &renderer.note(note.trim(), false);
+        }
+    }
+    let anchors = if args.toc || args.anchors || args.cross_link {
+        anchor_ids(&features, args.anchor_prefix.as_deref())
+    } else {
+        Vec::new()
+    };
+    if args.cross_link {
+        let names = features.iter().map(|f| f.name).zip(anchors.iter().map(String::as_str));
+        let names = names.collect::<HashMap<_, _>>();
+        for f in &mut features {
+            f.comment = cross_link(&f.comment, &names);
+            f.top = cross_link(&f.top, &names);
+        }
+    }
+    let mut toc = String::new();
+    if args.toc {
+        let headings = heading_anchors(&mut features, args.anchor_prefix.as_deref());
+        let mut entries = Vec::with_capacity(features.len() + headings.len());
+        for ((f, anchor), headings) in features.iter().zip(&anchors).zip(&headings) {
+            for (text, anchor) in headings {
+                entries.push(TocEntry { text, anchor, heading: true });
+            }
+            entries.push(TocEntry { text: f.name, anchor, heading: false });
+        }
+        toc = renderer.toc(&entries);
+    }
+    for (i, feature) in features.into_iter().enumerate() {
+        let default = default_features.contains(feature.name);
+        let anchor = anchors.get(i).map(String::as_str);
+        list += &renderer.feature(i, feature, default, levels[i], anchor);
+    }
+    list += &renderer.end();
+    if args.hide_footers {
+        top_comment.clear();
+    }
+    let footers = renderer.comment(&top_comment);
+    let mut generated_note = String::new();
+    if let Some(note) = &args.generated_note {
+        generated_note = renderer.generated_note(note.trim());
+    }
+    let mut result = String::with_capacity(list.len() + footers.len());
+    for section in args.layout.as_deref().unwrap_or(DEFAULT_LAYOUT) {
+        result += match section {
+            Section::Legend => &legend_note,
+            Section::Summary => &summary,
+            Section::DefaultNote => &default_note,
+            Section::Toc => &toc,
+            Section::Features => &list,
+            Section::Footers => &footers,
+            Section::GeneratedNote => &generated_note,
+        };
+    }
+    match &args.wrapper_class {
+        Some(class) if !class.is_empty() && matches!(args.format, Format::Html | Format::Dl) => {
+            Ok(format!("<div class=\"{}\">\n{}</div>\n", html_escape(class), result))
+        }
+        _ => Ok(result),
+    }
+}
+
+/// Writes the list of features, for the formats that produce a list
+trait Renderer {
+    /// A note before the list, such as the requirements summary
+    fn note(&self, text: &str, emphasis: bool) -> String {
+        if emphasis {
+            format!("_{}_\n\n", text)
+        } else {
+            format!("{}\n\n", text)
+        }
+    }
+    /// The note after the list added by `show_generated_note`
+    fn generated_note(&self, text: &str) -> String {
+        format!("\n_{}_\n", text)
+    }
+    /// The `#! ` comments, in markdown
+    fn comment(&self, text: &str) -> String {
+        text.into()
+    }
+    /// The feature at position `index` in the list, nested at `level`. `default` is true for the
+    /// features enabled by default, even with `show_default = false`. The `anchor` is the id of
+    /// the feature, to link to it from the table of contents.
+    fn feature(
+        &self,
+        index: usize,
+        feature: Feature,
+        default: bool,
+        level: usize,
+        anchor: Option<&str>,
+    ) -> String;
+    /// The table of contents, with the features and the headings of the `#! ` comments
+    fn toc(&self, entries: &[TocEntry]) -> String {
+        let mut result = String::new();
+        for e in entries {
+            if e.heading {
+                writeln!(result, "* [{}](#{})", e.text, e.anchor).unwrap();
+            } else {
+                let name = substitute_label("`{feature}`", e.text);
+                writeln!(result, "* [{}](#{})", name, e.anchor).unwrap();
+            }
+        }
+        result.push('\n');
+        result
+    }
+    /// The end of the list
+    fn end(&self) -> String {
+        String::new()
+    }
+}
+
+/// An entry of the table of contents of `toc`
+struct TocEntry<'a> {
+    /// The name of the feature, or the text of the heading
+    text: &'a str,
+    anchor: &'a str,
+    heading: bool,
+}
+
+/// The default markdown output
+struct Markdown<'a> {
+    args: &'a Args,
+}
+
+impl Renderer for Markdown<'_> {
+    fn feature(
+        &self,
+        index: usize,
+        feature: Feature,
+        default: bool,
+        level: usize,
+        anchor: Option<&str>,
+    ) -> String {
+        let args = self.args;
+        let is_default = default && !args.hide_default;
+        let feature_label = match (default, &args.default_feature_label) {
+            (true, Some(label)) => label.as_str(),
+            _ => args.feature_label.as_deref().unwrap_or("**`{feature}`**"),
+        };
+        let default_icon = match &args.default_icon {
+            Some(icon) if default && !icon.is_empty() => format!("{} ", icon),
+            _ => String::new(),
+        };
+        let stability = match feature.stability() {
+            "stable" => String::new(),
+            stability => format!(" *({})*", stability),
+        };
+        let alternatives = match feature.tag("alternative-to") {
+            Some(alternatives) => {
+                let alternatives = alternatives
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .map(|a| format!("`{}`", a))
+                    .collect::<Vec<_>>();
+                format!("\n\n  Alternative to: {}", alternatives.join(", "))
+            }
+            None => String::new(),
+        };
+        let default_on = match feature.tag("default-on") {
+            Some(platforms) => {
+                let platforms = platforms.split(',').map(str::trim).filter(|p| !p.is_empty());
+                format!("\n\n  Enabled by default on: {}", platforms.collect::<Vec<_>>().join(", "))
+            }
+            None => String::new(),
+        };
+        let non_additive = if feature.tag("non-additive").is_some() {
+            "\n\n  ⚠️ *This feature is not additive: it changes the behavior of the crate.*"
+        } else {
+            ""
+        };
+        let items = feature
+            .tags
+            .iter()
+            .filter(|(n, _)| *n == "enables-item")
+            .flat_map(|(_, v)| v.split(','))
+            .map(str::trim)
+            .filter(|i| !i.is_empty())
+            .map(|i| format!("[`{}`]", i))
+            .collect::<Vec<_>>();
+        let items = if items.is_empty() {
+            String::new()
+        } else {
+            format!("\n\n  Enables: {}", items.join(", "))
+        };
+        let source = if args.show_source && !feature.source.is_empty() {
+            let lines = feature.source.lines().collect::<Vec<_>>().join("\n  ");
+            format!(
+                    "\n\n  <details><summary>Cargo.toml</summary>\n\n  ```toml\n  {}\n  ```\n\n  </details>",
+                    lines
+                )
+        } else {
+            String::new()
+        };
+        let dependency =
+            if args.mark_dependencies && feature.dependency { " *(dependency)*" } else { "" };
+        let target = if args.annotate_target && !feature.targets.is_empty() {
+            let targets = feature.targets.iter().map(|t| format!("`{}`", t)).collect::<Vec<_>>();
+            format!(" *(only for {})*", targets.join(" or "))
+        } else {
+            String::new()
+        };
+        let icon = feature.tag("icon").map(|i| format!("{} ", i)).unwrap_or_default();
+        let Feature { name: f, mut top, mut comment, .. } = feature;
+        if args.normalize_case {
+            comment = normalize_case(&comment);
+        }
+        if let Some(separator) = &args.group_separator {
+            // A group starts at the `#!` comments that are not before the first feature
+            if index > 0 && !top.is_empty() {
+                top = format!("\n{}\n{}", separator, top);
+            }
+        }
+        if args.join == Join::Space {
+            let lines = comment.lines().map(str::trim).filter(|l| !l.is_empty());
+            comment = format!("  {}", lines.collect::<Vec<_>>().join(" "));
+        }
+        let marker = default_marker(args);
+        let (default_prefix, default) = match (is_default, args.default_marker_position) {
+            (false, _) => (String::new(), String::new()),
+            (true, MarkerPosition::Suffix) => (String::new(), format!(" {}", marker)),
+            (true, MarkerPosition::Prefix) => (format!("{} ", marker), String::new()),
+        };
+        let doc = match args.max_description_chars {
+            Some(max) => truncate_description(comment.trim(), max),
+            None => Cow::Borrowed(comment.trim()),
+        };
+        let line = if let Some(template) = &args.line_template {
+            let default = if is_default { format!(" {}", marker) } else { String::new() };
+            template
+                .replace("{label}", &substitute_label(feature_label, f))
+                .replace("{name}", f)
+                .replace("{default}", &default)
+                .replace("{doc}", &doc)
+        } else if let Some(template) = &args.template {
+            let default = if is_default { format!(" {}", marker) } else { String::new() };
+            // `{doc}` is replaced last so that the description is kept verbatim
+            template.replace("{feature}", f).replace("{default}", &default).replace("{doc}", &doc)
+        } else {
+            let comment = if doc.is_empty() {
+                String::new()
+            } else if let Some(template) = &args.description_template {
+                template.replace("{name}", f).replace("{doc}", &doc)
+            } else if let Some(separator) = &args.separator {
+                format!("{} {}", separator, doc)
+            } else if args.max_description_chars.is_some() {
+                format!(" —  {}", doc)
+            } else {
+                format!(" —{}", comment.trim_end())
+            };
+            let anchor = anchor.map(|a| format!("<a id=\"{}\"></a>", a)).unwrap_or_default();
+            format!(
+                "{} {}{}{}{}{}{}{}{}{}{}",
+                args.bullet.as_deref().unwrap_or("*"),
+                anchor,
+                default_icon,
+                default_prefix,
+                icon,
+                substitute_label(feature_label, f),
+                default,
+                stability,
+                dependency,
+                target,
+                comment,
+            )
+        };
+
+        let mut item = String::new();
+        writeln!(
+            item,
+            "{}{}{}{}{}{}{}",
+            top, line, non_additive, default_on, alternatives, items, source
+        )
+        .unwrap();
+        if level == 0 {
+            return item;
+        }
+        let indent = "  ".repeat(level);
+        let mut result = String::new();
+        for line in item.lines() {
+            if !line.is_empty() {
+                result += &indent;
+            }
+            result += line;
+            result.push('\n');
+        }
+        result
+    }
+}
+
+/// The marker of the default features in the markdown output
+fn default_marker(args: &Args) -> String {
+    match (&args.default_label, args.default_marker_emphasis) {
+        (Some(label), emphasis) => emphasis.unwrap_or(Emphasis::None).wrap(label),
+        (None, emphasis) => emphasis.unwrap_or(Emphasis::Italic).wrap("(enabled by default)"),
+    }
+}
+
+/// The text of `legend = true`: the markers that the markdown output has for these features
+fn legend(features: &[Feature], default_features: &HashSet<String>, args: &Args) -> String {
+    let has_default = features.iter().any(|f| default_features.contains(f.name));
+    let has_tag = |tag: &str| features.iter().any(|f| f.tag(tag).is_some());
+    let has_stability = |level: &str| features.iter().any(|f| f.stability() == level);
+    let mut entries = Vec::new();
+    match &args.default_icon {
+        Some(icon) if has_default && !icon.is_empty() => {
+            entries.push(format!("{} enabled by default", icon))
+        }
+        _ => (),
+    }
+    if has_default && !args.hide_default && args.line_template.is_none() && args.template.is_none()
+    {
+        entries.push(format!("{} enabled by the `default` feature", default_marker(args)));
+    }
+    if has_stability("experimental") {
+        entries.push("*(experimental)* may change in a minor release".into());
+    }
+    if has_stability("deprecated") {
+        entries.push("*(deprecated)* will be removed in a future release".into());
+    }
+    if args.mark_dependencies && features.iter().any(|f| f.dependency) {
+        entries.push("*(dependency)* an optional dependency".into());
+    }
+    if args.annotate_target && features.iter().any(|f| !f.targets.is_empty()) {
+        entries.push("*(only for …)* only available for some targets".into());
+    }
+    if has_tag("non-additive") {
+        entries.push("⚠️ not additive, changes the behavior of the crate".into());
+    }
+    if entries.is_empty() {
+        String::new()
+    } else {
+        format!("Legend: {}.", entries.join(" · "))
+    }
+}
+
+/// The output of `format = "table"`: a markdown table per group of features
+struct Table<'a> {
+    args: &'a Args,
+}
+
+impl Renderer for Table<'_> {
+    fn feature(
+        &self,
+        index: usize,
+        feature: Feature,
+        default: bool,
+        _level: usize,
+        anchor: Option<&str>,
+    ) -> String {
+        let args = self.args;
+        let mut result = String::new();
+        // The `#!` comments are written between the tables
+        if index == 0 || !feature.top.is_empty() {
+            if index > 0 && !feature.top.starts_with('\n') {
+                result.push('\n');
+            }
+            if !feature.top.is_empty() {
+                writeln!(result, "{}", feature.top).unwrap();
+            }
+            result += "| Feature | Default | Description |\n|---|:---:|---|\n";
+        }
+        let feature_label = match (default, &args.default_feature_label) {
+            (true, Some(label)) => label.as_str(),
+            _ => args.feature_label.as_deref().unwrap_or("**`{feature}`**"),
+        };
+        let stability = match feature.stability() {
+            "stable" => String::new(),
+            stability => format!(" *({})*", stability),
+        };
+        let marker = if default && !args.hide_default { "✓" } else { "" };
+        let lines = feature.comment.lines().map(str::trim).filter(|l| !l.is_empty());
+        let doc = lines.collect::<Vec<_>>().join(" ").replace('|', "\\|");
+        let anchor = anchor.map(|a| format!("<a id=\"{}\"></a>", a)).unwrap_or_default();
+        writeln!(
+            result,
+            "| {}{}{} | {} | {} |",
+            anchor,
+            substitute_label(feature_label, feature.name),
+            stability,
+            marker,
+            doc
+        )
+        .unwrap();
+        result
+    }
+}
+
+/// The output of `format = "html"`: a `<ul>` list per group of features, or a `<dl>` definition
+/// list with `format = "dl"`
+struct Html<'a> {
+    args: &'a Args,
+    definitions: bool,
+}
+
+impl Html<'_> {
+    fn list_tag(&self) -> &'static str {
+        if self.definitions {
+            "dl"
+        } else {
+            "ul"
+        }
+    }
+
+    /// The opening tag of a list, with its role for `accessible`
+    fn list_start(&self, tag: &str) -> String {
+        if self.args.accessible && tag == "ul" {
+            format!("<{} role=\"list\">\n", tag)
+        } else {
+            format!("<{}>\n", tag)
+        }
+    }
+}
+
+impl Renderer for Html<'_> {
+    fn note(&self, text: &str, emphasis: bool) -> String {
+        if emphasis {
+            format!("<p><em>{}</em></p>\n", html_escape(text))
+        } else {
+            format!("<p>{}</p>\n", html_escape(text))
+        }
+    }
+
+    fn generated_note(&self, text: &str) -> String {
+        self.note(text, true)
+    }
+
+    fn comment(&self, text: &str) -> String {
+        let mut result = String::new();
+        let mut paragraph = Vec::new();
+        // a blank line ends the paragraph, which is also flushed at the end
+        for line in text.lines().map(str::trim).chain(std::iter::once("")) {
+            let level = line.bytes().take_while(|b| *b == b'#').count();
+            if level > 0 && level <= 6 && line[level..].starts_with(' ') {
+                if !paragraph.is_empty() {
+                    result += &self.note(&paragraph.join(" "), false);
+                    paragraph.clear();
+                }
+                let heading = line[level..].trim();
+                // The anchor added by `heading_anchors` becomes the id of the heading
+                let anchored = heading
+                    .strip_prefix("<a id=\"")
+                    .and_then(|h| h.split_once("\"></a>"))
+                    .map(|(id, h)| (format!(" id=\"{}\"", id), h));
+                let (id, heading) = anchored.unwrap_or_else(|| (String::new(), heading));
+                let heading = html_escape(heading);
+                writeln!(result, "<h{}{}>{}</h{}>", level, id, heading, level).unwrap();
+            } else if line.is_empty() {
+                if !paragraph.is_empty() {
+                    result += &self.note(&paragraph.join(" "), false);
+                    paragraph.clear();
+                }
+            } else {
+                paragraph.push(line);
+            }
+        }
+        result
+    }
+
+    fn feature(
+        &self,
+        index: usize,
+        feature: Feature,
+        default: bool,
+        _level: usize,
+        anchor: Option<&str>,
+    ) -> String {
+        let mut result = String::new();
+        if index == 0 || !feature.top.is_empty() {
+            if index > 0 {
+                writeln!(result, "</{}>", self.list_tag()).unwrap();
+            }
+            result += &self.comment(&feature.top);
+            result += &self.list_start(self.list_tag());
+        }
+        let tag = if self.definitions { "dt" } else { "li" };
+        write!(result, "<{}", tag).unwrap();
+        if let Some(anchor) = anchor {
+            write!(result, " id=\"{}\"", anchor).unwrap();
+        }
+        if self.args.data_attributes {
+            write!(result, " data-default=\"{}\"", default).unwrap();
+            write!(result, " data-optional-dep=\"{}\"", feature.dependency).unwrap();
+            if let Some(since) = feature.tag("since") {
+                write!(result, " data-since=\"{}\"", html_escape(since)).unwrap();
+            }
+        }
+        write!(result, "><code>{}</code>", html_escape(feature.name)).unwrap();
+        if default && !self.args.hide_default {
+            let label = self.args.default_label.as_deref().unwrap_or("(enabled by default)");
+            if self.args.accessible {
+                result += " <em aria-label=\"enabled by default\">";
+            } else {
+                result += " <em>";
+            }
+            write!(result, "{}</em>", html_escape(label)).unwrap();
+        }
+        match feature.stability() {
+            "stable" => (),
+            stability => write!(result, " <em>({})</em>", stability).unwrap(),
+        }
+        let lines = feature.comment.lines().map(str::trim).filter(|l| !l.is_empty());
+        let doc = lines.collect::<Vec<_>>().join(" ");
+        if self.definitions {
+            writeln!(result, "</dt><dd>{}</dd>", html_escape(&doc)).unwrap();
+        } else {
+            if !doc.is_empty() {
+                write!(result, " — {}", html_escape(&doc)).unwrap();
+            }
+            result += "</li>\n";
+        }
+        result
+    }
+
+    fn end(&self) -> String {
+        format!("</{}>\n", self.list_tag())
+    }
+
+    fn toc(&self, entries: &[TocEntry]) -> String {
+        let mut result = self.list_start("ul");
+        for e in entries {
+            let text = html_escape(e.text);
+            let text = if e.heading { text } else { format!("<code>{}</code>", text) };
+            writeln!(result, "<li><a href=\"#{}\">{}</a></li>", e.anchor, text).unwrap();
+        }
+        result += "</ul>\n";
+        result
+    }
+}
+
+/// The output of `format = "rst"`: a reStructuredText bullet list
+struct Rst<'a> {
+    args: &'a Args,
+}
+
+impl Renderer for Rst<'_> {
+    fn note(&self, text: &str, emphasis: bool) -> String {
+        if emphasis {
+            format!("*{}*\n\n", text)
+        } else {
+            format!("{}\n\n", text)
+        }
+    }
+
+    fn generated_note(&self, text: &str) -> String {
+        format!("\n*{}*\n", text)
+    }
+
+    /// Each heading or paragraph is preceded by an empty line, the markdown headings are turned
+    /// into titles underlined according to their level
+    fn comment(&self, text: &str) -> String {
+        let mut result = String::new();
+        let mut in_paragraph = false;
+        for line in text.lines().map(str::trim) {
+            let level = line.bytes().take_while(|b| *b == b'#').count();
+            if level > 0 && level <= 6 && line[level..].starts_with(' ') {
+                let title = line[level..].trim();
+                let underline = ["=", "-", "~", "^", "\"", "'"][level - 1];
+                let underline = underline.repeat(title.chars().count());
+                write!(result, "\n{}\n{}\n", title, underline).unwrap();
+                in_paragraph = false;
+            } else if line.is_empty() {
+                in_paragraph = false;
+            } else {
+                if !in_paragraph {
+                    result.push('\n');
+                }
+                writeln!(result, "{}", line).unwrap();
+                in_paragraph = true;
+            }
+        }
+        result
+    }
+
+    fn feature(
+        &self,
+        index: usize,
+        feature: Feature,
+        default: bool,
+        _level: usize,
+        _anchor: Option<&str>,
+    ) -> String {
+        let mut result = String::new();
+        if !feature.top.is_empty() {
+            let comment = self.comment(&feature.top);
+            // the first comment doesn't need to be separated from the previous list
+            result += if index == 0 { comment.trim_start_matches('\n') } else { &comment };
+            result.push('\n');
+        }
+        write!(result, "* ``{}``", feature.name).unwrap();
+        if default && !self.args.hide_default {
+            let label = self.args.default_label.as_deref().unwrap_or("(enabled by default)");
+            write!(result, " *{}*", label).unwrap();
+        }
+        match feature.stability() {
+            "stable" => (),
+            stability => write!(result, " *({})*", stability).unwrap(),
+        }
+        let mut lines = feature.comment.trim().lines().map(str::trim);
+        if let Some(first) = lines.next() {
+            write!(result, " — {}", first).unwrap();
+        }
+        result.push('\n');
+        // the continuation lines are aligned with the text of the item
+        for line in lines {
+            if !line.is_empty() {
+                result += "  ";
+            }
+            writeln!(result, "{}", line).unwrap();
+        }
+        result
+    }
+}
+
+/// The output of `format = "text"`: plain text with a `name (default) - description` line per
+/// feature
+struct Text<'a> {
+    args: &'a Args,
+}
+
+impl Renderer for Text<'_> {
+    fn note(&self, text: &str, _emphasis: bool) -> String {
+        format!("{}\n", text_comment(text))
+    }
+
+    fn generated_note(&self, text: &str) -> String {
+        format!("\n{}", text_comment(text))
+    }
+
+    fn comment(&self, text: &str) -> String {
+        text_comment(text)
+    }
+
+    fn feature(
+        &self,
+        _index: usize,
+        feature: Feature,
+        default: bool,
+        _level: usize,
+        _anchor: Option<&str>,
+    ) -> String {
+        let mut result = text_comment(&feature.top);
+        result += feature.name;
+        if default && !self.args.hide_default {
+            result += " (default)";
+        }
+        if feature.stability() != "stable" {
+            write!(result, " ({})", feature.stability()).unwrap();
+        }
+        let comment = text_comment(&feature.comment);
+        let lines = comment.lines().map(str::trim).filter(|l| !l.is_empty());
+        let doc = lines.collect::<Vec<_>>().join(" ");
+        if !doc.is_empty() {
+            write!(result, " - {}", doc).unwrap();
+        }
+        result.push('\n');
+        result
+    }
+}
+
+/// Escape the characters that have a meaning in HTML
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The ids of the anchors of the features: `feature-` followed by the name of the feature in
+/// lowercase, where the characters other than letters and digits are replaced by `-`.
+/// A number is added to the ids that would otherwise be the same, such as for `a.b` and `a-b`.
+fn anchor_ids(features: &[Feature], prefix: Option<&str>) -> Vec<String> {
+    let prefix = prefix.map_or_else(String::new, |p| format!("{}-", slugify(p)));
+    let mut used = HashSet::with_capacity(features.len());
+    let names = features.iter().map(|f| format!("feature-{}{}", prefix, slugify(f.name)));
+    names.map(|id| unique_id(&mut used, id)).collect()
+}
+
+/// Add an anchor to the headings of the `#! ` comments before the features, for `toc`, and
+/// return the text and the id of these headings for each feature. The id is `section-` followed
+/// by the text of the heading like for the features, whatever the level of the heading, so
+/// `### Experimental` and `#### Experimental` get `section-experimental` and
+/// `section-experimental-2`.
+fn heading_anchors(features: &mut [Feature], prefix: Option<&str>) -> Vec<Vec<(String, String)>> {
+    let prefix = prefix.map_or_else(String::new, |p| format!("{}-", slugify(p)));
+    let mut used = HashSet::new();
+    let mut result = Vec::with_capacity(features.len());
+    for f in features {
+        let mut headings = Vec::new();
+        let mut top = String::with_capacity(f.top.len());
+        for line in f.top.lines() {
+            let trimmed = line.trim_start();
+            let level = trimmed.bytes().take_while(|b| *b == b'#').count();
+            match trimmed[level..].strip_prefix(' ') {
+                Some(text) if (1..=6).contains(&level) && !text.trim().is_empty() => {
+                    let text = text.trim();
+                    let id = format!("section-{}{}", prefix, slugify(text));
+                    let id = unique_id(&mut used, id);
+                    let indent = &line[..line.len() - trimmed.len()];
+                    let hashes = &trimmed[..level];
+                    writeln!(top, "{}{} <a id=\"{}\"></a>{}", indent, hashes, id, text).unwrap();
+                    headings.push((text.to_string(), id));
+                }
+                _ => writeln!(top, "{}", line).unwrap(),
+            }
+        }
+        if !headings.is_empty() {
+            f.top = top;
+        }
+        result.push(headings);
+    }
+    result
+}
+
+/// The name in lowercase, where the characters other than letters and digits are replaced by `-`
+fn slugify(name: &str) -> String {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// `id`, or `id` followed by `-2`, `-3`, ... if it is already in `used`
+fn unique_id(used: &mut HashSet<String>, id: String) -> String {
+    let mut unique = id.clone();
+    let mut n = 2;
+    while used.contains(&unique) {
+        unique = format!("{}-{}", id, n);
+        n += 1;
+    }
+    used.insert(unique.clone());
+    unique
+}
+
+/// Turn the code spans of `text` that are the name of a feature, such as `` `tls` ``, into links
+/// to the anchor of that feature. The code spans that are already links are left as they are.
+fn cross_link(text: &str, anchors: &HashMap<&str, &str>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('`') {
+        result += &rest[..start];
+        let after = &rest[start..];
+        let len = match after[1..].find('`') {
+            Some(len) => len,
+            None => break,
+        };
+        let span = &after[..len + 2];
+        rest = &after[len + 2..];
+        match anchors.get(&span[1..=len]) {
+            Some(anchor) if !result.ends_with('[') => {
+                write!(result, "[{}](#{})", span, anchor).unwrap()
+            }
+            _ => result += span,
+        }
+    }
+    result += rest;
+    result
+}
+
+/// The values of the `@requires`, `@platform` and `@msrv` tags of all the features, without
+/// duplicates, in the order in which they appear
+fn requirements(features: &[Feature]) -> Vec<String> {
+    let mut result = Vec::new();
+    for f in features {
+        for (tag, value) in &f.tags {
+            let prefix = match *tag {
+                "requires" | "platform" => "",
+                "msrv" => "Rust ",
+                _ => continue,
+            };
+            for v in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+                let v = format!("{}{}", prefix, v);
+                if !result.contains(&v) {
+                    result.push(v);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Reorder the features so that each feature comes after the first feature it enables, for
+/// `indent_dependents`. Returns the features and how deep they are nested.
+/// A feature with `#!` comments before it starts a group and is never nested. A feature is not
+/// nested under a feature that it is enabled by, directly or not, so that cycles are broken.
+fn nest_dependents<'a>(
+    features: Vec<Feature<'a>>,
+    enables: &[(&str, Vec<String>)],
+) -> (Vec<Feature<'a>>, Vec<usize>) {
+    let index = features.iter().enumerate().map(|(i, f)| (f.name, i)).collect::<HashMap<_, _>>();
+    let enables = enables.iter().map(|(f, deps)| (*f, deps)).collect::<HashMap<_, _>>();
+    let mut parents: Vec<Option<usize>> = vec![None; features.len()];
+    // Union-find of the trees built so far: `i` is still the root of its tree when it gets a
+    // parent, so there would be a cycle exactly when the parent is already in the same tree.
+    let mut trees = (0..features.len()).collect::<Vec<_>>();
+    fn find(trees: &mut [usize], mut i: usize) -> usize {
+        while trees[i] != i {
+            trees[i] = trees[trees[i]];
+            i = trees[i];
+        }
+        i
+    }
+    for (i, f) in features.iter().enumerate() {
+        if !f.top.is_empty() {
+            continue;
+        }
+        let candidates = enables.get(f.name).into_iter().flat_map(|deps| deps.iter());
+        for dep in candidates {
+            let dep = dep.strip_prefix("dep:").unwrap_or(dep);
+            let parent = match index.get(dep) {
+                Some(parent) if *parent != i => *parent,
+                _ => continue,
+            };
+            let (tree, parent_tree) = (find(&mut trees, i), find(&mut trees, parent));
+            if tree != parent_tree {
+                trees[tree] = parent_tree;
+                parents[i] = Some(parent);
+                break;
+            }
+        }
+    }
+    let mut children = vec![Vec::new(); features.len()];
+    let mut stack = Vec::new();
+    for (i, parent) in parents.iter().enumerate().rev() {
+        match parent {
+            Some(parent) => children[*parent].push(i),
+            None => stack.push((i, 0)),
+        }
+    }
+    // Depth first traversal, keeping the order of declaration between siblings
+    let mut order = Vec::with_capacity(features.len());
+    while let Some((i, level)) = stack.pop() {
+        order.push((i, level));
+        stack.extend(children[i].iter().map(|c| (*c, level + 1)));
+    }
+    let mut features = features.into_iter().map(Some).collect::<Vec<_>>();
+    let levels = order.iter().map(|(_, level)| *level).collect();
+    let features = order.iter().filter_map(|(i, _)| features[*i].take()).collect();
+    (features, levels)
+}
+
+/// Capitalize the first letter of a description and end it with a period.
+/// Markdown characters, like the backtick of a code span, have no uppercase and are kept as is.
+fn normalize_case(text: &str) -> String {
+    let trimmed = text.trim_end();
+    let content = trimmed.trim_start();
+    if content.is_empty() {
+        return text.into();
+    }
+    let mut result = String::with_capacity(text.len() + 1);
+    result += &trimmed[..trimmed.len() - content.len()];
+    let mut chars = content.chars();
+    result.extend(chars.next().into_iter().flat_map(char::to_uppercase));
+    result += chars.as_str();
+    let ends_code_block = trimmed.lines().last().map_or(false, |l| l.trim().starts_with("```"));
+    if !ends_code_block && !trimmed.ends_with(|c| ".!?:;…".contains(c)) {
+        result.push('.');
+    }
+    result += &text[trimmed.len()..];
+    result
+}
+
+/// Group the features by stability, each group with a heading, in the order of `STABILITY_LEVELS`.
+/// Within a group, the features keep the order of declaration. The `#!` comments are removed.
+fn sort_by_stability(features: Vec<Feature>) -> Vec<Feature> {
+    let mut result = Vec::with_capacity(features.len());
+    let mut groups = STABILITY_LEVELS.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    for mut f in features {
+        f.top.clear();
+        let level = STABILITY_LEVELS.iter().position(|l| *l == f.stability()).unwrap_or(0);
+        groups[level].push(f);
+    }
+    for (level, group) in STABILITY_LEVELS.iter().zip(groups) {
+        let separator = if result.is_empty() { "" } else { "\n" };
+        for (i, mut f) in group.into_iter().enumerate() {
+            if i == 0 {
+                let mut heading = level.to_string();
+                heading[..1].make_ascii_uppercase();
+                f.top = format!("{}### {} features\n", separator, heading);
+            }
+            result.push(f);
+        }
+    }
+    result
+}
+
+/// Sort the features by name within each section, a section starting at a feature with `#!`
+/// comments, which stay at the start of the section.
+fn sort_alphabetically(features: Vec<Feature>) -> Vec<Feature> {
+    fn sort_section(section: &mut [Feature]) {
+        let top = section.first_mut().map(|f| std::mem::take(&mut f.top)).unwrap_or_default();
+        section.sort_by(|a, b| a.name.cmp(b.name));
+        if let Some(first) = section.first_mut() {
+            first.top = top;
+        }
+    }
+    let mut result: Vec<Feature> = Vec::with_capacity(features.len());
+    let mut section_start = 0;
+    for f in features {
+        if !f.top.is_empty() {
+            sort_section(&mut result[section_start..]);
+            section_start = result.len();
+        }
+        result.push(f);
+    }
+    sort_section(&mut result[section_start..]);
+    result
+}
+
+/// Only keep the features for which `keep` returns true.
+/// The `#!` comments before a feature that is removed move to the next feature that is kept,
+/// unless they are followed by other `#!` comments first.
+fn filter_features<'a>(
+    features: Vec<Feature<'a>>,
+    keep: impl Fn(&Feature) -> bool,
+) -> Vec<Feature<'a>> {
+    let mut result = Vec::new();
+    let mut top = String::new();
+    for mut f in features {
+        if !f.top.is_empty() {
+            top = std::mem::take(&mut f.top);
+        }
+        if keep(&f) {
+            f.top = std::mem::take(&mut top);
+            // The separation with the previous feature only makes sense if there is one
+            if result.is_empty() {
+                f.top = f.top.trim_start_matches('\n').into();
+            } else if !f.top.is_empty() && !f.top.starts_with('\n') {
+                f.top.insert(0, '\n');
+            }
+            result.push(f);
+        }
+    }
+    result
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any sequence of characters and
+/// `?` matches any single character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    // The position after the last `*` in the pattern, and the position in the name that it matches
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Generate the code of the enum for `document_features_enum!`
+fn generate_enum(name: &str, features: &[Feature]) -> Result<String, String> {
+    let mut code = format!(
+        "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]\n\
+         /// The documented features of this crate\n\
+         pub enum {} {{\n",
+        name
+    );
+    let mut all = String::new();
+    let mut as_str = String::new();
+    for (f, variant) in features.iter().zip(item_names(features)?) {
+        // The Debug representation of a string is a valid string literal
+        writeln!(code, "    #[doc = {:?}]\n    {},", f.comment.trim(), variant).unwrap();
+        write!(all, "{}::{}, ", name, variant).unwrap();
+        writeln!(as_str, "            {}::{} => {:?},", name, variant, f.name).unwrap();
+    }
+    write!(
+        code,
+        "}}\n\
+         impl {name} {{\n\
+         /// All the features, in the order they are declared in Cargo.toml\n\
+         pub const ALL: &'static [{name}] = &[{all}];\n\
+         /// The name of the feature in Cargo.toml\n\
+         pub fn as_str(self) -> &'static str {{\n\
+         match self {{\n{as_str}}}\n\
+         }}\n\
+         }}\n\
+         impl ::core::fmt::Display for {name} {{\n\
+         fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {{\n\
+         f.write_str(self.as_str())\n\
+         }}\n\
+         }}\n",
+        name = name,
+        all = all,
+        as_str = as_str
+    )
+    .unwrap();
+    Ok(code)
+}
+
+/// Generate the code of the module for `document_features_items!`
+fn generate_items(module: &str, features: &[Feature]) -> Result<String, String> {
+    let mut code = format!(
+        "/// The documented features of this crate, with one item per feature\n\
+         #[doc(hidden)]\n\
+         pub mod {} {{\n\
+         #![allow(dead_code, non_camel_case_types)]\n",
+        module
+    );
+    for (f, item) in features.iter().zip(item_names(features)?) {
+        // The Debug representation of a string is a valid string literal
+        writeln!(
+            code,
+            "    #[doc = {:?}]\n    pub struct {item};\n    impl {item} {{\n\
+             \x20       /// The name of the feature in Cargo.toml\n\
+             \x20       pub const NAME: &'static str = {:?};\n    }}",
+            f.comment.trim(),
+            f.name,
+            item = item
+        )
+        .unwrap();
+    }
+    code += "}\n";
+    Ok(code)
+}
+
+/// The CamelCase identifiers for the features, with an error if two features have the same
+fn item_names(features: &[Feature]) -> Result<Vec<String>, String> {
+    let mut names = HashMap::new();
+    for f in features {
+        if !f.name.chars().any(char::is_alphanumeric) {
+            return Err(format!("Feature `{}` has no letters or digits for an identifier", f.name));
+        }
+        let name = variant_name(f.name);
+        if let Some(previous) = names.insert(name.clone(), f.name) {
+            return Err(format!(
+                "Features `{}` and `{}` have the same identifier `{}`",
+                previous, f.name, name
+            ));
+        }
+    }
+    Ok(features.iter().map(|f| variant_name(f.name)).collect())
+}
+
+/// Convert a feature name into a CamelCase identifier.
+/// `self` becomes `Self_`, since `Self` is a keyword.
+fn variant_name(feature: &str) -> String {
+    let mut result = String::new();
+    for part in feature.split(|c: char| !c.is_alphanumeric()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            result.extend(first.to_uppercase());
+            result.extend(chars);
+        }
+    }
+    if result.chars().next().map_or(true, |c| c.is_numeric()) {
+        result.insert(0, '_');
+    }
+    if result == "Self" {
+        result.push('_');
+    }
+    result
+}
+
+/// Split the `[lang]` tag from a comment line such as ` [de] Beschreibung`.
+/// Returns the language and the line without the tag.
+fn split_lang(line: &str) -> Option<(&str, String)> {
+    let trimmed = line.trim_start();
+    let (lang, rest) = trimmed.strip_prefix('[')?.split_once(']')?;
+    let (code, region) = lang.split_once('-').unwrap_or((lang, "AA"));
+    let is_lang = (2..=3).contains(&code.len())
+        && code.bytes().all(|b| b.is_ascii_lowercase())
+        && (2..=4).contains(&region.len())
+        && region.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !is_lang || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let indent = &line[..line.len() - trimmed.len()];
+    Some((lang, format!("{}{}", indent, rest.strip_prefix(' ').unwrap_or(rest))))
+}
+
+/// Select the lines of a comment in the given language.
+/// Lines can be tagged with a language (`[de] ...`); if there are lines in `lang`, only these are
+/// kept, otherwise the lines without tag, otherwise the `en` lines. Empty lines are kept at the
+/// start, or after a line that is kept.
+fn localize(comment: &str, lang: &str) -> String {
+    if !comment.lines().any(|l| split_lang(l).is_some()) {
+        return comment.to_string();
+    }
+    let has_lang =
+        |l: &str| comment.lines().any(|line| split_lang(line).map_or(false, |s| s.0 == l));
+    let has_untagged =
+        comment.lines().any(|line| !line.trim().is_empty() && split_lang(line).is_none());
+    let selected = if has_lang(lang) {
+        Some(lang)
+    } else if has_untagged {
+        None
+    } else {
+        Some("en")
+    };
+    let mut result = String::with_capacity(comment.len());
+    let mut keep_empty = true;
+    for line in comment.lines() {
+        let line = match split_lang(line) {
+            Some((l, line)) if Some(l) == selected => line,
+            None if line.trim().is_empty() && keep_empty => line.to_string(),
+            None if !line.trim().is_empty() && selected.is_none() => line.to_string(),
+            _ => {
+                keep_empty = false;
+                continue;
+            }
+        };
+        keep_empty = true;
+        result += &line;
+        result.push('\n');
+    }
+    result
+}
+
+/// Replace `{feature}` by the name of the feature in the label.
+/// If the label puts the name within a code span and the name contains backticks, the code span
+/// is delimited with more backticks than the name contains so that it is not broken.
+fn substitute_label(label: &str, name: &str) -> String {
+    if name.contains('`') && label.contains("`{feature}`") {
+        let mut longest = 0;
+        let mut current = 0;
+        for c in name.chars() {
+            current = if c == '`' { current + 1 } else { 0 };
+            longest = longest.max(current);
+        }
+        let delimiter = "`".repeat(longest + 1);
+        let code = format!("{} {} {}", delimiter, name, delimiter);
+        label.replace("`{feature}`", &code).replace("{feature}", name)
+    } else {
+        label.replace("{feature}", name)
+    }
+}
+
+/// Truncate the description to at most `max` characters (plus an ellipsis) at a word boundary,
+/// without cutting in the middle of an inline code span.
+fn truncate_description(text: &str, max: usize) -> Cow<'_, str> {
+    if text.chars().count() <= max {
+        return Cow::Borrowed(text);
+    }
+    let mut in_code = false;
+    let mut cut = None;
+    for (count, (idx, c)) in text.char_indices().enumerate() {
+        if c == '`' {
+            in_code = !in_code;
+        } else if c.is_whitespace() && !in_code {
+            if count > max && cut.is_some() {
+                break;
+            }
+            cut = Some(idx);
+        }
+    }
+    match cut {
+        Some(cut) => Cow::Owned(format!("{}…", text[..cut].trim_end())),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Remove the markdown of comments: headings are in uppercase, and the code spans, emphasis and
+/// links are replaced by their text
+fn text_comment(comment: &str) -> String {
+    let mut result = String::with_capacity(comment.len());
+    for line in comment.lines().map(str::trim) {
+        let level = line.bytes().take_while(|b| *b == b'#').count();
+        if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+            result += &strip_markdown(line[level..].trim()).to_uppercase();
+        } else {
+            result += &strip_markdown(line);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// Replace the code spans, emphasis and links of a line of markdown by their text.
+/// The `*` and `_` are only removed at the start or at the end of a word, so that `snake_case`
+/// and `2 * 3` are kept.
+fn strip_markdown(line: &str) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(line.len());
+    let mut in_code = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '`' {
+            in_code = !in_code;
+            i += 1;
+        } else if in_code {
+            result.push(c);
+            i += 1;
+        } else if c == '*' || c == '_' {
+            let end = i + chars[i..].iter().take_while(|x| **x == c).count();
+            let before = if i > 0 { chars[i - 1] } else { ' ' };
+            let after = chars.get(end).copied().unwrap_or(' ');
+            let opens = !after.is_whitespace() && !before.is_alphanumeric();
+            let closes = !before.is_whitespace() && !after.is_alphanumeric();
+            if !opens && !closes {
+                result.extend(&chars[i..end]);
+            }
+            i = end;
+        } else if c == '[' {
+            // `[text](url)`, `[text][ref]`, or an intra-doc link such as [`Foo`]
+            let close = chars[i..].iter().position(|x| *x == ']').map(|p| i + p);
+            let target_end = close.and_then(|close| {
+                let delimiter = match chars.get(close + 1) {
+                    Some('(') => ')',
+                    Some('[') => ']',
+                    _ => return None,
+                };
+                chars[close + 1..].iter().position(|x| *x == delimiter).map(|p| close + 1 + p)
+            });
+            match (close, target_end) {
+                (Some(close), Some(end)) => {
+                    result += &strip_markdown(&chars[i + 1..close].iter().collect::<String>());
+                    i = end + 1;
+                }
+                (Some(close), None) if chars[i + 1] == '`' && chars[close - 1] == '`' => {
+                    result += &strip_markdown(&chars[i + 1..close].iter().collect::<String>());
+                    i = close + 1;
+                }
+                _ => {
+                    result.push(c);
+                    i += 1;
+                }
+            }
+        } else {
+            result.push(c);
+            i += 1;
+        }
+    }
+    result
+}
+
+/// Render a table with a row per feature and a column per tier, telling if the feature is part
+/// of the tier, directly or because it is enabled by another feature of the tier
+fn render_tiers(
+    features: &[Feature],
+    tiers: &[(String, Vec<String>)],
+    enables: &[(&str, Vec<String>)],
+    args: &Args,
+) -> Result<String, String> {
+    if tiers.is_empty() {
+        return Err("No tiers in [package.metadata.document-features.tiers]".into());
+    }
+    let dependencies =
+        enables.iter().map(|(f, deps)| (f.to_string(), deps.clone())).collect::<HashMap<_, _>>();
+    let members = tiers
+        .iter()
+        .map(|(_, tier)| {
+            let mut members = HashSet::new();
+            for f in tier {
+                dependents(&dependencies, f.strip_prefix("dep:").unwrap_or(f), &mut members);
+            }
+            members
+        })
+        .collect::<Vec<_>>();
+    let feature_label = args.feature_label.as_deref().unwrap_or("**`{feature}`**");
+    let mut result = String::from("| Feature |");
+    for (name, _) in tiers {
+        write!(result, " {} |", name).unwrap();
+    }
+    result += "\n|---|";
+    result += &":---:|".repeat(tiers.len());
+    result.push('\n');
+    for f in features {
+        write!(result, "| {} |", substitute_label(feature_label, f.name)).unwrap();
+        for members in &members {
+            result += if members.contains(f.name) { " ✓ |" } else { " ✗ |" };
+        }
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Render a Mermaid graph where there is an edge from each feature to the features it enables.
+/// Dependencies (`dep:foo`) and features of dependencies (`foo/bar`) have the `dep` style.
+fn render_mermaid(enables: &[(&str, Vec<String>)]) -> String {
+    let mut result = String::from("```mermaid\ngraph TD\n");
+    let mut ids = HashMap::new();
+    let mut node = |name: &str, result: &mut String| -> String {
+        let next_id = ids.len();
+        ids.entry(name.to_string())
+            .or_insert_with(|| {
+                let label = name.strip_prefix("dep:").unwrap_or(name).replace('"', "#quot;");
+                let id = format!("f{}", next_id);
+                if name.starts_with("dep:") || name.contains('/') {
+                    writeln!(result, "    {}([\"{}\"]):::dep", id, label).unwrap();
+                } else {
+                    writeln!(result, "    {}[\"{}\"]", id, label).unwrap();
+                }
+                id
+            })
+            .clone()
+    };
+    for (feature, _) in enables {
+        node(feature, &mut result);
+    }
+    for (feature, deps) in enables {
+        let from = node(feature, &mut result);
+        for d in deps {
+            let to = node(d, &mut result);
+            writeln!(result, "    {} --> {}", from, to).unwrap();
+        }
+    }
+    result += "    classDef dep stroke-dasharray: 5 5\n```\n";
+    result
+}
+
+/// Render the features as a single line of shields.io style badges
+fn render_badges<'a>(
+    features: impl Iterator<Item = &'a str>,
+    default_features: &HashSet<String>,
+    args: &Args,
+) -> String {
+    let url = args.badge_url.as_deref().unwrap_or("https://img.shields.io/badge");
+    let url = url.trim_end_matches('/');
+    let badges = features
+        .map(|f| {
+            let color = if default_features.contains(f) {
+                args.badge_default_color.as_deref().unwrap_or("brightgreen")
+            } else {
+                args.badge_color.as_deref().unwrap_or("blue")
+            };
+            format!("![{}]({}/feature-{}-{})", f, url, shields_escape(f), color)
+        })
+        .collect::<Vec<_>>();
+    badges.join(" ") + "\n"
+}
+
+/// Escape some text so it can be used within the path of a shields.io badge URL
+fn shields_escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '-' => result += "--",
+            '_' => result += "__",
+            ' ' => result.push('_'),
+            c if c.is_ascii_alphanumeric() || c == '.' || c == '~' => result.push(c),
+            c => {
+                for b in c.to_string().bytes() {
+                    write!(result, "%{:02X}", b).unwrap();
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Get the value that starts at `first_line`, taking more lines from `lines` until the brackets
+/// and braces are balanced and the multi-line strings are closed. The comments are removed.
+fn get_balanced<'a>(
+    first_line: &'a str,
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<Cow<'a, str>, String> {
+    let mut line = first_line;
+    let mut result = Cow::from("");
+
+    // The delimiter of the string we are in: `"`, `'`, `"""` or `'''`
+    let mut quote: Option<&str> = None;
+    let mut level = 0;
+    loop {
+        let bytes = line.as_bytes();
+        let mut idx = 0;
+        while idx < bytes.len() {
+            match quote {
+                // Only the basic strings have escape sequences
+                Some(q) if bytes[idx] == b'\\' && q.starts_with('"') => idx += 1,
+                Some(q) if bytes[idx..].starts_with(q.as_bytes()) => {
+                    idx += q.len() - 1;
+                    quote = None;
+                }
+                Some(_) => (),
+                None => match bytes[idx] {
+                    b'\\' => idx += 1,
+                    b @ b'"' | b @ b'\'' => {
+                        let triple = if b == b'"' { "\"\"\"" } else { "\'\'\'" };
+                        let q = if bytes[idx..].starts_with(triple.as_bytes()) {
+                            triple
+                        } else {
+                            &triple[..1]
+                        };
+                        idx += q.len() - 1;
+                        quote = Some(q);
+                    }
+                    b'{' | b'[' => level += 1,
+                    b'}' | b']' if level == 0 => return Err("unbalanced source".into()),
+                    b'}' | b']' => level -= 1,
+                    b'#' => {
+                        line = &line[..idx];
+                        break;
+                    }
+                    _ => (),
+                },
+            }
+            idx += 1;
+        }
+        // Only the multi-line strings continue on the next line
+        if quote.map_or(false, |q| q.len() == 1) {
+            quote = None;
+        }
+        if result.is_empty() {
+            result = Cow::from(line);
+        } else {
+            *result.to_mut() += line;
+        }
+        if level == 0 && quote.is_none() {
+            return Ok(result);
+        }
+        line = if let Some(l) = lines.next() {
+            l
+        } else {
+            return Err("unbalanced source".into());
+        };
+    }
+}
+
+#[test]
+fn test_get_balanced() {
+    assert_eq!(
+        get_balanced(
+            "{",
+            &mut IntoIterator::into_iter(["a", "{ abc[], #ignore", " def }", "}", "xxx"])
+        ),
+        Ok("{a{ abc[],  def }}".into())
+    );
+    assert_eq!(
+        get_balanced("{ foo = \"{#\" } #ignore", &mut IntoIterator::into_iter(["xxx"])),
+        Ok("{ foo = \"{#\" } ".into())
+    );
+    assert_eq!(
+        get_balanced("]", &mut IntoIterator::into_iter(["["])),
+        Err("unbalanced source".into())
+    );
+    assert_eq!(
+        get_balanced("\"\"\"a", &mut IntoIterator::into_iter(["## b ]", "c\"\"\" # d", "xxx"])),
+        Ok("\"\"\"a## b ]c\"\"\" ".into())
+    );
+    assert_eq!(
+        get_balanced("'''\\", &mut IntoIterator::into_iter(["'''", "xxx"])),
+        Ok("'''\\'''".into())
+    );
+    assert_eq!(
+        get_balanced("['a\"', \"b'\"] # c", &mut IntoIterator::into_iter(["xxx"])),
+        Ok("['a\"', \"b'\"] ".into())
+    );
+}
+
+#[cfg(feature = "self-test")]
+#[proc_macro]
+#[doc(hidden)]
+/// Helper macro for the tests. Do not use
+pub fn self_test_helper(input: TokenStream) -> TokenStream {
+    let mut token_trees = input.into_iter();
+    let toml = token_trees.next().map(|tt| tt.to_string()).unwrap_or_default();
+    // The arguments of `document_features!` can follow the Cargo.toml, after a comma
+    let rest = token_trees.skip_while(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ','));
+    let mut code = String::new();
+    for line in toml.trim_matches(|c| c == '"' || c == '#').lines() {
+        // Rustdoc removes the lines that starts with `# ` and removes one `#` from lines that starts with # followed by space.
+        // We need to re-add the `#` that was removed by rustdoc to get the original.
+        if line.strip_prefix('#').map_or(false, |x| x.is_empty() || x.starts_with(' ')) {
+            code += "#";
+        }
+        code += line;
+        code += "\n";
+    }
+    parse_args(rest.collect())
+        .and_then(|macro_args| resolve_args(&code, macro_args))
+        .and_then(|args| process_toml(&code, &args).map_err(|e| error(&e)))
+        .map_or_else(std::convert::identity, |r| {
+            std::iter::once(proc_macro::TokenTree::from(proc_macro::Literal::string(&r))).collect()
+        })
+}
+
+#[cfg(feature = "self-test")]
+macro_rules! self_test {
+    (#[doc = $toml:literal] $(, $arg:ident = $value:expr)* => #[doc = $md:literal]) => {
+        concat!(
+            "\n`````rust\n\
+            fn normalize_md(md : &str) -> String {
+               md.lines().skip_while(|l| l.is_empty()).map(|l| l.trim())
+                .collect::<Vec<_>>().join(\"\\n\")
+            }
+            assert_eq!(normalize_md(document_features::self_test_helper!(",
+            stringify!($toml),
+            $(", ", stringify!($arg = $value),)*
+            ")), normalize_md(",
+            stringify!($md),
+            "));\n`````\n\n"
+        )
+    };
+}
+
+#[cfg(not(feature = "self-test"))]
+macro_rules! self_test {
+    (#[doc = $toml:literal] => #[doc = $md:literal]) => {
+        concat!(
+            "This contents in Cargo.toml:\n`````toml",
+            $toml,
+            "\n`````\n Generates the following:\n\
+            <table><tr><th>Preview</th></tr><tr><td>\n\n",
+            $md,
+            "\n</td></tr></table>\n\n&nbsp;\n",
+        )
+    };
+    (#[doc = $toml:literal] $(, $arg:ident = $value:expr)+ => #[doc = $md:literal]) => {
+        concat!(
+            "This contents in Cargo.toml:\n`````toml",
+            $toml,
+            "\n`````\n With `document_features!(",
+            stringify!($($arg = $value),+),
+            ")`, generates the following:\n\
+            <table><tr><th>Preview</th></tr><tr><td>\n\n",
+            $md,
+            "\n</td></tr></table>\n\n&nbsp;\n",
+        )
+    };
+}
+
+use self_test;
+
+// The following struct is inserted only during generation of the documentation in order to exploit doc-tests.
+// These doc-tests are used to check that invalid arguments to the `document_features!` macro cause a compile time error.
+// For a more principled way of testing compilation error, maybe investigate <https://docs.rs/trybuild>.
+//
+/// ```rust
+/// #![doc = document_features::document_features!()]
+/// #![doc = document_features::document_features!(feature_label = "**`{feature}`**")]
+/// #![doc = document_features::document_features!(feature_label = r"**`{feature}`**")]
+/// #![doc = document_features::document_features!(feature_label = r#"**`{feature}`**"#)]
+/// #![doc = document_features::document_features!(feature_label = "<span class=\"stab portability\"><code>{feature}</code></span>")]
+/// #![doc = document_features::document_features!(feature_label = r#"<span class="stab portability"><code>{feature}</code></span>"#)]
+/// #![doc = document_features::document_features!(feature_label = "**`{feature}`**", format = "markdown")]
+/// #![doc = document_features::document_features!(format = "badges", badge_url = "https://img.shields.io/badge", badge_color = "red", badge_default_color = "green",)]
+/// #![doc = document_features::document_features!(max_description_chars = 80, no_default_note = true)]
+/// #![doc = document_features::document_features!(join = "space", prefix_filter = "self-")]
+/// #![doc = document_features::document_features!(comment_position = "above", sort = "stability")]
+/// #![doc = document_features::document_features!(section_intro = true, default_marker_position = "prefix")]
+/// #![doc = document_features::document_features!(format = "mermaid", lang = "en")]
+/// #![doc = document_features::document_features!(show_generated_note = true)]
+/// #![doc = document_features::document_features!(default_label = "(default)", default_marker_emphasis = "code")]
+/// #![doc = document_features::document_features!(layout = ["features", "footers"])]
+/// #![doc = document_features::document_features!(layout = ["summary", "features",])]
+/// #![doc = document_features::document_features!(show_generated_note = "Generated, do not edit")]
+/// #![doc = document_features::document_features!(template = "- `{feature}`{default}: {doc}")]
+/// #![doc = document_features::document_features!(bullet = "-")]
+/// #![doc = document_features::document_features!(implicit_features_note = true)]
+/// #![doc = document_features::document_features!(separator = ":")]
+/// #![doc = document_features::document_features!(format = "html")]
+/// #![doc = document_features::document_features!(format = "table")]
+/// #![doc = document_features::document_features!(format = "dl")]
+/// #![doc = document_features::document_features!(note_no_defaults = true)]
+/// #![doc = document_features::document_features!(format = "rst")]
+/// #![doc = document_features::document_features!(format = "html", data_attributes = true)]
+/// #![doc = document_features::document_features!(format = "html", accessible = true)]
+/// #![doc = document_features::document_features!(toc = true)]
+/// #![doc = document_features::document_features!(layout = ["features", "toc"])]
+/// #![doc = document_features::document_features!(anchors = true)]
+/// #![doc = document_features::document_features!(document_dev_deps = true)]
+/// #![doc = document_features::document_features!(cross_link = true)]
+/// #![doc = document_features::document_features!(manifest_path = "Cargo.toml")]
+/// #![doc = document_features::document_features!(format = "html", wrapper_class = "features")]
+/// #![doc = document_features::document_features!(group_non_additive = true)]
+/// #![doc = document_features::document_features!(sort = "alphabetical")]
+/// #![doc = document_features::document_features!(include = "self-*, std")]
+/// #![doc = document_features::document_features!(exclude = "internal-*, __*")]
+/// #![doc = document_features::document_features!(require_all_documented = true)]
+/// #![doc = document_features::document_features!(default_icon = "★")]
+/// #![doc = document_features::document_features!(line_template = "- {label}{default} — {doc}")]
+/// #![doc = document_features::document_features!(include_undocumented = "*Not documented*")]
+/// #![doc = document_features::document_features!(legend = true, layout = ["features", "legend"])]
+/// #![doc = document_features::document_features!(validate_dep_refs = true)]
+/// #![doc = document_features::document_features!(default_feature_label = "**{feature}**", show_default = false)]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(feature_label > "<span>{feature}</span>")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(label = "<span>{feature}</span>")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(feature_label = "{feat}")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(template = "- {doc}")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(manifest_path = "does-not-exist.toml")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(default_feature_label = "**default**")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(feature_label = 3.14)]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(feature_label = )]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(feature_label = "**`{feature}`**" extra)]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(format = "unknown")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(format = "badges", format = "markdown")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(format = "badges" badge_color = "red")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(no_default_note = "yes")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(show_generated_note = 1)]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(layout = "features")]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(layout = ["features", "features"])]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(workspace = true)]
+/// ```
+/// ```compile_fail
+/// #![doc = document_features::document_features!(format = "html", normalize_case = true)]
+/// ```
+#[cfg(doc)]
+struct FeatureLabelCompilationTest;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_toml, process_toml, Args, CommentPosition, Emphasis, Format, Join, MarkerPosition,
+        Section, Sort,
+    };
+    use std::fmt::Write;
+
+    #[track_caller]
+    fn test_error(toml: &str, expected: &str) {
+        let err = process_toml(toml, &Args::default()).unwrap_err();
+        assert!(err.contains(expected), "{:?} does not contain {:?}", err, expected)
+    }
+
+    /// A new directory in the temporary directory, removed when dropped even if the test fails
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "document-features-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = std::path::Path;
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn only_get_balanced_in_correct_table() {
+        process_toml(
+            r#"
+
+[package.metadata.release]
+pre-release-replacements = [
+  {test=\"\#\# \"},
+]
+[abcd]
+[features]#xyz
+#! abc
+#
+###
+#! def
+#!
+## 123
+## 456
+feat1 = ["plop"]
+#! ghi
+no_doc = []
+##
+feat2 = ["momo"]
+#! klm
+default = ["feat1", "something_else"]
+#! end
+            "#,
+            &Args::default(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn doc_comments_in_strings() {
+        let toml = r###"
+[package]
+name = "foo"
+version = "1.0.0"
+description = "## not a comment"
+
+[package.metadata.release]
+pre-release-replacements = [
+  {file="CHANGELOG.md", search="Unreleased", replace="{{version}}", min=1},
+  {file="CHANGELOG.md", search="<!-- next-header -->", replace="""<!-- next-header -->
+## [Unreleased] - ReleaseDate
+""", exactly=1},
+]
+changelog-header = '''
+#! Changelog
+## [Unreleased] - ReleaseDate
+'''
+changelog = """
+foo
+# [Unreleased] - ReleaseDate"""
+[features]
+## Foo
+foo = []
+[dependencies]
+## Bar
+bar = { version = "1", optional = true }
+        "###;
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`foo`** —  Foo\n* **`bar`** —  Bar\n"
+        );
+    }
+
+    #[test]
+    fn multi_line_inline_table() {
+        let toml = r#"
+[dependencies]
+## Foo
+foo = {
+    version = "1",
+    features = ["optional-extras", { a = "b" }],
+    optional = true
+}
+## Bar
+bar = { version = "1", features = ["a", "b"], optional = true }
+[features]
+## Baz
+baz = ["foo/optional-extras"]
+        "#;
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`foo`** —  Foo\n* **`bar`** —  Bar\n* **`baz`** —  Baz\n"
+        );
+        let toml = "[dependencies]\n## Foo\nfoo = { version = \"1\", features = [\n\
+                    \"optional\",\n] }\n";
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap_err(),
+            "Dependency foo is not an optional dependency"
+        );
+        assert!(super::is_optional("{ features = [\"optional\"], optional = true }"));
+        assert!(super::is_optional("{version=\"1\",optional=true}"));
+        assert!(!super::is_optional("{ optional = false, features = [\"optional = true\"] }"));
+    }
+
+    #[test]
+    fn hash_in_quoted_value() {
+        let toml = r##"
+[dependencies]
+## Foo
+foo = { version = "1.0#beta", optional = true } # the beta
+## Bar
+bar = { version = '2.0#rc', optional = true }
+## Baz
+baz = { git = "https://example.com/baz#{x}", branch = "a]b#c", optional = true }
+        "##;
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`foo`** —  Foo\n* **`bar`** —  Bar\n* **`baz`** —  Baz\n"
+        );
+    }
+
+    #[test]
+    fn no_features() {
+        let r = process_toml(
+            r#"
+[features]
+[dependencies]
+foo = 4;
+"#,
+            &Args::default(),
+        )
+        .unwrap();
+        assert_eq!(r, "*No documented features in Cargo.toml*");
+    }
+
+    #[test]
+    fn no_features2() {
+        let r = process_toml(
+            r#"
+[packages]
+[dependencies]
+"#,
+            &Args::default(),
+        )
+        .unwrap();
+        assert_eq!(r, "*No documented features in Cargo.toml*");
+    }
+
+    #[test]
+    fn parse_error3() {
+        test_error(
+            r#"
+[features]
+ff = []
+[abcd
+efgh
+[dependencies]
+"#,
+            "Parse error while parsing line: [abcd",
+        );
+    }
+
+    #[test]
+    fn parse_error4() {
+        test_error(
+            r#"
+[features]
+## dd
+## ff
+#! ee
+## ff
+"#,
+            "A feature doc comment (##) was immediately followed by a group comment (#!)",
+        );
+    }
+
+    #[test]
+    fn doc_comment_before_group_comment() {
+        test_error(
+            "[features]\n## doc\n#! group\nfeature = []\n",
+            "A feature doc comment (##) was immediately followed by a group comment (#!) before \
+             any feature; move the group comment or add the feature first.",
+        );
+    }
+
+    #[test]
+    fn parse_error5() {
+        test_error(
+            r#"
+[features]
+## dd
+"#,
+            "not associated with a feature",
+        );
+    }
+
+    #[test]
+    fn parse_error6() {
+        // The array is never closed, see `comments_in_default_array` for the comments
+        test_error(
+            r#"
+[features]
+# ff
+foo = []
+default = [
+#ffff
+# ff
+"#,
+            "Parse error while parsing value default",
+        );
+    }
+
+    #[test]
+    fn comments_in_default_array() {
+        let toml = r#"
+[features]
+# ff
+foo = []
+## Bar
+bar = []
+default = [
+#ffff
+# ff
+    "foo", # the foo feature
+
+    ## not a doc comment
+    #! nor this
+    "bar",
+]
+## Baz
+baz = []
+"#;
+        let expected = "* **`bar`** *(enabled by default)* —  Bar\n* **`baz`** —  Baz\n";
+        assert_eq!(process_toml(toml, &Args::default()).unwrap(), expected);
+        let args = Args { strict_comment_blocks: true, ..Args::default() };
+        assert_eq!(process_toml(toml, &args).unwrap(), expected);
+        let args = Args { require_default_docs: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Default features without documentation: `foo`"
+        );
+    }
+
+    #[test]
+    fn parse_error7() {
+        test_error(
+            r#"
+[features]
+# f
+foo = [ x = { ]
+bar = []
+"#,
+            "Parse error while parsing value foo",
+        );
+    }
+
+    #[test]
+    fn not_a_feature1() {
+        test_error(
+            r#"
+## hallo
+[features]
+"#,
+            "Not a feature: `[features]`",
+        );
+    }
+
+    #[test]
+    fn not_a_feature2() {
+        test_error(
+            r#"
+[package]
+## hallo
+foo = []
+"#,
+            "Comment cannot be associated with a feature: \"hallo\"",
+        );
+    }
+
+    #[test]
+    fn non_optional_dep1() {
+        test_error(
+            r#"
+[dev-dependencies]
+## Not optional
+foo = { version = "1.2", optional = false }
+"#,
+            "Dependency foo is not an optional dependency",
+        );
+    }
+
+    #[test]
+    fn non_optional_dep2() {
+        test_error(
+            r#"
+[dev-dependencies]
+## Not optional
+foo = { version = "1.2" }
+"#,
+            "Dependency foo is not an optional dependency",
+        );
+    }
+
+    #[test]
+    fn basic() {
+        let toml = r#"
+[abcd]
+[features]#xyz
+#! abc
+#
+###
+#! def
+#!
+## 123
+## 456
+feat1 = ["plop"]
+#! ghi
+no_doc = []
+##
+feat2 = ["momo"]
+#! klm
+default = ["feat1", "something_else"]
+#! end
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            " abc\n def\n\n* **`feat1`** *(enabled by default)* —  123\n  456\n\n ghi\n* **`feat2`**\n\n klm\n end\n"
+        );
+        let parsed = process_toml(
+            toml,
+            &Args {
+                feature_label: Some(
+                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
+                ),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            " abc\n def\n\n* <span class=\"stab portability\"><code>feat1</code></span> *(enabled by default)* —  123\n  456\n\n ghi\n* <span class=\"stab portability\"><code>feat2</code></span>\n\n klm\n end\n"
+        );
+    }
+
+    #[test]
+    fn dependencies() {
+        let toml = r#"
+#! top
+[dev-dependencies] #yo
+## dep1
+dep1 = { version="1.2", optional=true}
+#! yo
+dep2 = "1.3"
+## dep3
+[target.'cfg(unix)'.build-dependencies.dep3]
+version = "42"
+optional = true
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, " top\n* **`dep1`** —  dep1\n\n yo\n* **`dep3`** —  dep3\n");
+        let parsed = process_toml(
+            toml,
+            &Args {
+                feature_label: Some(
+                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
+                ),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(parsed, " top\n* <span class=\"stab portability\"><code>dep1</code></span> —  dep1\n\n yo\n* <span class=\"stab portability\"><code>dep3</code></span> —  dep3\n");
+    }
+
+    #[test]
+    fn multi_lines() {
+        let toml = r#"
+[package.metadata.foo]
+ixyz = [
+    ["array"],
+    [
+        "of",
+        "arrays"
+    ]
+]
+[dev-dependencies]
+## dep1
+dep1 = {
+    version="1.2-}",
+    optional=true
+}
+[features]
+default = [
+    "goo",
+    "\"]",
+    "bar",
+]
+## foo
+foo = [
+   "bar"
+]
+## bar
+bar = [
+
+]
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`dep1`** —  dep1\n* **`foo`** —  foo\n* **`bar`** *(enabled by default)* —  bar\n"
+        );
+        let parsed = process_toml(
+            toml,
+            &Args {
+                feature_label: Some(
+                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
+                ),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            "* <span class=\"stab portability\"><code>dep1</code></span> —  dep1\n* <span class=\"stab portability\"><code>foo</code></span> —  foo\n* <span class=\"stab portability\"><code>bar</code></span> *(enabled by default)* —  bar\n"
+        );
+    }
+
+    #[test]
+    fn dots_in_feature() {
+        let toml = r#"
+[features]
+## This is a test
+"teßt." = []
+default = ["teßt."]
+[dependencies]
+## A dep
+"dep" = { version = "123", optional = true }
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`teßt.`** *(enabled by default)* —  This is a test\n* **`dep`** —  A dep\n"
+        );
+        let parsed = process_toml(
+            toml,
+            &Args {
+                feature_label: Some(
+                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
+                ),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            "* <span class=\"stab portability\"><code>teßt.</code></span> *(enabled by default)* —  This is a test\n* <span class=\"stab portability\"><code>dep</code></span> —  A dep\n"
+        );
+    }
+
+    #[test]
+    fn recursive_default() {
+        let toml = r#"
+[features]
+default=["qqq"]
+
+## Qqq
+qqq=["www"]
+
+## Www
+www=[]
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`qqq`** *(enabled by default)* —  Qqq\n* **`www`** *(enabled by default)* —  Www\n");
+    }
+
+    #[test]
+    fn no_trailing_newline() {
+        let parsed = process_toml("[features]\n## Foo\nfoo = []", &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n");
+        let parsed =
+            process_toml("[features]\n## Foo\nfoo = []\nbar = []", &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n");
+        let parsed =
+            process_toml("[features]\nfoo = []\n## Bar\nbar = [\"foo\"]", &Args::default())
+                .unwrap();
+        assert_eq!(parsed, "* **`bar`** —  Bar\n");
+        let parsed = process_toml(
+            "[dependencies]\n## Dep\n[dependencies.dep]\nversion = \"1\"\noptional = true",
+            &Args::default(),
+        )
+        .unwrap();
+        assert_eq!(parsed, "* **`dep`** —  Dep\n");
+        let parsed =
+            process_toml("[features]\n## Foo\nfoo = []\n#! end", &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n\n end\n");
+        test_error("[features]\nfoo = []\n## Foo", "not associated with a feature");
+    }
+
+    #[test]
+    fn badges() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+## Bar
+bar_baz = []
+[dependencies]
+## A dep
+"my-dep" = { version = "1", optional = true }
+        "#;
+        let args = Args { format: Format::Badges, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "![foo](https://img.shields.io/badge/feature-foo-brightgreen) \
+             ![bar_baz](https://img.shields.io/badge/feature-bar__baz-blue) \
+             ![my-dep](https://img.shields.io/badge/feature-my--dep-blue)\n"
+        );
+        let args = Args {
+            format: Format::Badges,
+            badge_url: Some("https://example.com/badge/".into()),
+            badge_color: Some("grey".into()),
+            badge_default_color: Some("orange".into()),
+            ..Default::default()
+        };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "![foo](https://example.com/badge/feature-foo-orange) \
+             ![bar_baz](https://example.com/badge/feature-bar__baz-grey) \
+             ![my-dep](https://example.com/badge/feature-my--dep-grey)\n"
+        );
+    }
+
+    #[test]
+    fn max_description_chars() {
+        let toml = r#"
+[features]
+## Enables the `foo_bar_baz` module which is very long
+foo = []
+## Short
+bar = []
+        "#;
+        let args = Args { max_description_chars: Some(25), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Enables the `foo_bar_baz`…\n* **`bar`** —  Short\n");
+        let args = Args { max_description_chars: Some(20), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Enables the…\n* **`bar`** —  Short\n");
+    }
+
+    #[test]
+    fn no_default_note() {
+        let toml = r#"
+[features]
+default = ["foo", "bar", "dep:dep", "dep/std"]
+## Foo
+foo = ["baz"]
+## Bar
+bar = []
+baz = []
+[dependencies]
+dep = { version = "1", optional = true }
+        "#;
+        let args = Args { no_default_note: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "Disabling default features turns off: `bar`, `baz`, `dep`, `foo`.\n\n\
+             * **`foo`** *(enabled by default)* —  Foo\n* **`bar`** *(enabled by default)* —  Bar\n"
+        );
+        let parsed = process_toml("[features]\n## Foo\nfoo = []", &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n");
+    }
+
+    #[test]
+    fn only_dependencies_with_groups() {
+        let toml = r#"
+[package]
+name = "foo"
+[dependencies]
+#! ### Group A
+## dep1
+dep1 = { version = "1", optional = true }
+not-optional = "1"
+#! ### Group B
+## dep2
+dep2 = { version = "1", optional = true }
+## dep3
+[dependencies.dep3]
+version = "1"
+optional = true
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            " ### Group A\n* **`dep1`** —  dep1\n\n ### Group B\n* **`dep2`** —  dep2\n* **`dep3`** —  dep3\n"
+        );
+    }
+
+    #[test]
+    fn generate_enum() {
+        let toml = r#"
+[features]
+## The foo feature
+foo-bar = []
+## Second
+"2d" = []
+[dependencies]
+## A "dep"
+my_dep = { version = "1", optional = true }
+        "#;
+        let parsed = parse_toml(toml, &Args::default()).unwrap();
+        let code = super::generate_enum("Features", &parsed.features).unwrap();
+        assert!(code.contains("pub enum Features {\n    #[doc = \"The foo feature\"]\n    FooBar,\n    #[doc = \"Second\"]\n    _2d,\n    #[doc = \"A \\\"dep\\\"\"]\n    MyDep,\n}"), "{}", code);
+        assert!(code.contains("Features::FooBar => \"foo-bar\","), "{}", code);
+        assert!(code.contains("&[Features::FooBar, Features::_2d, Features::MyDep, ]"), "{}", code);
+
+        let parsed =
+            parse_toml("[features]\n## a\nfoo-bar = []\n## b\nfoo_bar = []", &Args::default())
+                .unwrap();
+        assert_eq!(
+            super::generate_enum("Features", &parsed.features).unwrap_err(),
+            "Features `foo-bar` and `foo_bar` have the same identifier `FooBar`"
+        );
+
+        // `Self` is a keyword, and `_` is not an identifier
+        let parsed =
+            parse_toml("[features]\n## a\nself = []\n## b\nSelf_ = []", &Args::default()).unwrap();
+        assert_eq!(
+            super::generate_enum("Features", &parsed.features).unwrap_err(),
+            "Features `self` and `Self_` have the same identifier `Self_`"
+        );
+        let parsed = parse_toml("[features]\n## a\nself = []", &Args::default()).unwrap();
+        let code = super::generate_items("features", &parsed.features).unwrap();
+        assert!(code.contains("pub struct Self_;"), "{}", code);
+        let parsed = parse_toml("[features]\n## a\n_ = []", &Args::default()).unwrap();
+        assert_eq!(
+            super::generate_enum("Features", &parsed.features).unwrap_err(),
+            "Feature `_` has no letters or digits for an identifier"
+        );
+    }
+
+    #[test]
+    fn join_space() {
+        let toml = r#"
+[features]
+## First line
+##
+## second line
+foo = []
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  First line\n \n  second line\n");
+        let parsed = process_toml(toml, &Args { join: Join::Space, ..Default::default() }).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  First line second line\n");
+    }
+
+    #[test]
+    fn prefix_filter() {
+        let toml = r#"
+[features]
+## Foo
+foo = []
+## TLS with rustls
+tls-rustls = []
+#! ### Group without TLS
+## Bar
+bar = []
+#! ### Native
+## Baz
+baz = []
+## TLS with native-tls
+tls-native = []
+#! end
+        "#;
+        let args = Args { prefix_filter: Some("tls-".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`tls-rustls`** —  TLS with rustls\n\n ### Native\n* **`tls-native`** —  TLS with native-tls\n\n end\n"
+        );
+        let args = Args { prefix_filter: Some("xyz".into()), ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "No documented features start with `xyz`"
+        );
+    }
+
+    #[test]
+    fn comment_sequences() {
+        let toml = r#"
+[features]
+## Closes */ then opens /* with a \ backslash
+foo = []
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Closes */ then opens /* with a \\ backslash\n");
+    }
+
+    #[test]
+    fn comment_position_below() {
+        let toml = r#"
+[features]
+default = ["foo"]
+#! ### Group
+foo = []
+## Foo
+## on two lines
+undocumented = []
+bar = []
+## Bar
+#! ### Dependencies
+[dependencies]
+not-optional = "1"
+dep1 = { version = "1", optional = true }
+## Dep1
+[dependencies.dep2]
+## Dep2
+version = "1"
+optional = true
+        "#;
+        let args = Args { comment_position: CommentPosition::Below, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            " ### Group\n* **`foo`** *(enabled by default)* —  Foo\n  on two lines\n* **`bar`** —  Bar\n\n ### Dependencies\n* **`dep1`** —  Dep1\n* **`dep2`** —  Dep2\n"
+        );
+
+        let args = Args { comment_position: CommentPosition::Below, ..Default::default() };
+        let err = process_toml("[features]\n## Foo\nfoo = []", &args).unwrap_err();
+        assert_eq!(err, "Comment cannot be associated with a feature: \"Foo\"");
+        let err = process_toml("[dependencies]\nfoo = \"1\"\n## Foo", &args).unwrap_err();
+        assert_eq!(err, "Dependency foo is not an optional dependency");
+        let err = process_toml("[package]\nname = \"foo\"\n## Foo", &args).unwrap_err();
+        assert_eq!(err, "Comment cannot be associated with a feature: \"Foo\"");
+    }
+
+    #[test]
+    fn sort_stability() {
+        let toml = r#"
+[features]
+default = ["b"]
+#! ### Group
+## A
+## @stability: experimental
+a = []
+## B
+b = []
+## C
+## @stability: deprecated
+c = []
+## D
+## @stability: experimental
+d = []
+## E
+## @stability : stable
+## @unknown
+e = []
+#! end
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            " ### Group\n* **`a`** *(experimental)* —  A\n* **`b`** *(enabled by default)* —  B\n\
+             * **`c`** *(deprecated)* —  C\n* **`d`** *(experimental)* —  D\n\
+             * **`e`** —  E\n  @unknown\n\n end\n"
+        );
+        let args = Args { sort: Sort::Stability, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "### Stable features\n* **`b`** *(enabled by default)* —  B\n* **`e`** —  E\n  @unknown\n\
+             \n### Experimental features\n* **`a`** *(experimental)* —  A\n* **`d`** *(experimental)* —  D\n\
+             \n### Deprecated features\n* **`c`** *(deprecated)* —  C\n"
+        );
+        test_error("[features]\n## @stability: unstable\nfoo = []", "Unknown stability `unstable`");
+    }
+
+    #[test]
+    fn section_intro() {
+        let toml = r#"
+[package]
+name = "foo"
+## This is the features section
+[features]
+## Foo
+foo = []
+## Optional dependencies
+## of this crate
+[target.'cfg(unix)'.dependencies]
+## Dep
+dep = { version = "1", optional = true }
+        "#;
+        test_error(toml, "Not a feature: `[features]`");
+        let args = Args { section_intro: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            " This is the features section\n* **`foo`** —  Foo\n\n Optional dependencies\n of this crate\n* **`dep`** —  Dep\n"
+        );
+        let args = Args { section_intro: true, ..Default::default() };
+        let err = process_toml("## Package\n[package]\n", &args).unwrap_err();
+        assert_eq!(err, "Not a feature: `[package]`");
+    }
+
+    #[test]
+    fn default_marker_prefix() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+## Bar
+bar = []
+        "#;
+        let args = Args { default_marker_position: MarkerPosition::Prefix, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* *(enabled by default)* **`foo`** —  Foo\n* **`bar`** —  Bar\n");
+    }
+
+    #[test]
+    fn default_marker_emphasis() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+        "#;
+        for (emphasis, marker) in [
+            (Emphasis::Italic, "*(enabled by default)*"),
+            (Emphasis::Bold, "**(enabled by default)**"),
+            (Emphasis::Code, "`(enabled by default)`"),
+            (Emphasis::None, "(enabled by default)"),
+        ]
+        .iter()
+        {
+            let args = Args { default_marker_emphasis: Some(*emphasis), ..Default::default() };
+            let parsed = process_toml(toml, &args).unwrap();
+            assert_eq!(parsed, format!("* **`foo`** {} —  Foo\n", marker));
+            let args = Args { default_marker_position: MarkerPosition::Prefix, ..args };
+            let parsed = process_toml(toml, &args).unwrap();
+            assert_eq!(parsed, format!("* {} **`foo`** —  Foo\n", marker));
+        }
+    }
+
+    #[test]
+    fn profiles() {
+        let toml = r#"
+[package.metadata.document-features.profiles]
+minimal = ["foo"]
+full = [
+    "foo",
+    "bar",
+    "dep",
+]
+[package.metadata.document-features.profiles.other]
+features = ["bar"]
+[features]
+#! ### Group
+## Foo
+foo = []
+## Bar
+bar = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+        "#;
+        let args = Args { profile: Some("minimal".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, " ### Group\n* **`foo`** —  Foo\n");
+        let args = Args { profile: Some("full".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            " ### Group\n* **`foo`** —  Foo\n* **`bar`** —  Bar\n* **`dep`** —  Dep\n"
+        );
+        let args = Args { profile: Some("other".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, " ### Group\n* **`bar`** —  Bar\n");
+        let args = Args { profile: Some("max".into()), ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Unknown profile `max`, expected one of: full, minimal, other"
+        );
+    }
+
+    #[test]
+    fn warn_unknown_defaults() {
+        let toml = r#"
+[features]
+default = ["foo", "dep", "other/feat", "stale"]
+## Foo
+foo = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** *(enabled by default)* —  Foo\n* **`dep`** *(enabled by default)* —  Dep\n"
+        );
+        let args = Args { warn_unknown_defaults: true, ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "The `default` feature references `stale`, which is neither a feature nor a documented optional dependency"
+        );
+        let toml = toml.replace(", \"stale\"", "");
+        let parsed = process_toml(&toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** *(enabled by default)* —  Foo\n* **`dep`** *(enabled by default)* —  Dep\n"
+        );
+    }
+
+    #[test]
+    fn description_template() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## The foo feature
+## on two lines
+foo = []
+## The bar feature
+bar = []
+## Not rendered
+empty = []
+        "#;
+        let args =
+            Args { description_template: Some(" {name}: {doc}".into()), ..Default::default() };
+        let parsed = process_toml(&toml.replace("## Not rendered\n", ""), &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** *(enabled by default)* foo: The foo feature\n  on two lines\n\
+             * **`bar`** bar: The bar feature\n"
+        );
+        let args = Args {
+            description_template: Some(" — {doc} (`{name}`)".into()),
+            join: Join::Space,
+            ..Default::default()
+        };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** *(enabled by default)* — The foo feature on two lines (`foo`)\n\
+             * **`bar`** — The bar feature (`bar`)\n\
+             * **`empty`** — Not rendered (`empty`)\n"
+        );
+    }
+
+    #[test]
+    fn large_manifest() {
+        // Each feature enables the previous one, and they are all in a multi-line default array
+        const COUNT: usize = 20_000;
+        let mut toml = String::from("[features]\ndefault = [\n");
+        for i in 0..COUNT {
+            writeln!(toml, "    \"f{}\",", i).unwrap();
+        }
+        toml += "]\n";
+        for i in 0..COUNT {
+            let deps = if i == 0 { String::new() } else { format!("\"f{}\"", i - 1) };
+            writeln!(toml, "## Feature number {}\nf{} = [{}]", i, i, deps).unwrap();
+        }
+        let start = std::time::Instant::now();
+        let parsed = process_toml(&toml, &Args::default()).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(parsed.lines().count(), COUNT);
+        assert!(parsed.ends_with(&format!(
+            "* **`f{0}`** *(enabled by default)* —  Feature number {0}\n",
+            COUNT - 1
+        )));
+        assert!(elapsed < std::time::Duration::from_secs(5), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn alternative_to() {
+        let toml = r#"
+[features]
+## Use the software backend
+## @alternative-to: backend-gpu
+backend-software = []
+## Use the GPU backend
+## @alternative-to: backend-software, backend-vulkan
+##
+## Requires a GPU
+backend-gpu = []
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`backend-software`** —  Use the software backend\n\n  Alternative to: `backend-gpu`\n\
+             * **`backend-gpu`** —  Use the GPU backend\n \n  Requires a GPU\n\n  \
+             Alternative to: `backend-software`, `backend-vulkan`\n"
+        );
+    }
+
+    #[test]
+    fn show_source() {
+        let toml = r#"
+[features]
+## Foo
+foo = [
+    "bar", # with a comment
+    "dep",
+]
+## Bar
+bar = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+## Other
+[dependencies.other]
+version = "1"
+optional = true
+        "#;
+        let args = Args { show_source: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        let block = |s: &str| {
+            format!(
+                "\n\n  <details><summary>Cargo.toml</summary>\n\n  ```toml\n  {}\n  ```\n\n  </details>\n",
+                s
+            )
+        };
+        assert_eq!(
+            parsed,
+            format!(
+                "* **`foo`** —  Foo{}* **`bar`** —  Bar{}* **`dep`** —  Dep{}* **`other`** —  Other{}",
+                block("foo = [\n      \"bar\", # with a comment\n      \"dep\",\n  ]"),
+                block("bar = []"),
+                block("dep = { version = \"1\", optional = true }"),
+                block("[dependencies.other]\n  version = \"1\"\n  optional = true"),
+            )
+        );
+        let toml = r#"
+[features]
+foo = []
+## Foo
+[dependencies.other]
+## Other
+version = "1"
+optional = true
+        "#;
+        let args = Args { comment_position: CommentPosition::Below, ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            format!(
+                "* **`foo`** —  Foo{}* **`other`** —  Other{}",
+                block("foo = []"),
+                block("[dependencies.other]\n  ## Other\n  version = \"1\"\n  optional = true")
+            )
+        );
+    }
+
+    #[test]
+    fn group_separator() {
+        let toml = r#"
+[features]
+#! ### First group
+## Foo
+foo = []
+## Bar
+bar = []
+#! ### Second group
+## Baz
+baz = []
+#! The end
+        "#;
+        let args = Args { group_separator: Some("---".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            " ### First group\n* **`foo`** —  Foo\n* **`bar`** —  Bar\n\n---\n\n ### Second group\n\
+             * **`baz`** —  Baz\n\n The end\n"
+        );
+        let parsed = process_toml(&toml.replace("#! ### Second group\n", ""), &args).unwrap();
+        assert!(!parsed.contains("---"), "{}", parsed);
+    }
+
+    #[test]
+    fn comment_block_interrupted() {
+        let toml = r#"
+[features]
+## a
+# note
+## b
+foo = []
+## c
+# not an interruption
+bar = []
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  a\n  b\n* **`bar`** —  c\n");
+        let args = Args { strict_comment_blocks: true, ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            r##"The comment "# note" interrupts the ## comments: "a""##
+        );
+        for note in &["### note", "##note", "#!note"] {
+            let toml = toml.replace("# note", note);
+            assert_eq!(
+                process_toml(&toml, &Args::default()).unwrap(),
+                "* **`foo`** —  a\n  b\n* **`bar`** —  c\n"
+            );
+            assert_eq!(
+                process_toml(&toml, &args).unwrap_err(),
+                format!(r#"The comment "{}" interrupts the ## comments: "a""#, note)
+            );
+        }
+        let toml = toml.replace("# note\n", "");
+        let parsed = process_toml(&toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  a\n  b\n* **`bar`** —  c\n");
+        let args = Args { comment_position: CommentPosition::Below, ..args };
+        let toml = "[features]\nfoo = []\n## a\n# note\nbar = []\n## b\n#\n## c\n";
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            r##"The comment "#" interrupts the ## comments: "b""##
+        );
+    }
+
+    #[test]
+    fn mark_dependencies() {
+        let toml = r#"
+[features]
+default = ["dep"]
+## Foo
+foo = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+## Other
+[dependencies.other]
+version = "1"
+optional = true
+[target.'cfg(unix)'.dependencies]
+## Unix
+unix = { version = "1", optional = true }
+        "#;
+        let expected = "* **`foo`** —  Foo\n\
+                        * **`dep`** *(enabled by default)* *(dependency)* —  Dep\n\
+                        * **`other`** *(dependency)* —  Other\n\
+                        * **`unix`** *(dependency)* —  Unix\n";
+        let args = Args { mark_dependencies: true, ..Default::default() };
+        assert_eq!(process_toml(toml, &args).unwrap(), expected);
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, expected.replace(" *(dependency)*", ""));
+
+        let toml = r#"
+[features]
+foo = []
+## Foo
+[dependencies]
+dep = { version = "1", optional = true }
+## Dep
+[dependencies.other]
+## Other
+version = "1"
+optional = true
+        "#;
+        let args = Args { comment_position: CommentPosition::Below, ..args };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`foo`** —  Foo\n* **`dep`** *(dependency)* —  Dep\n\
+             * **`other`** *(dependency)* —  Other\n"
+        );
+    }
+
+    #[test]
+    fn annotate_target() {
+        let toml = r#"
+[features]
+## Foo
+foo = []
+[dependencies]
+## Everywhere
+everywhere = { version = "1", optional = true }
+[target.'cfg(windows)'.dependencies]
+## Windows
+winapi = { version = "1", optional = true }
+everywhere = { version = "1", features = ["windows"], optional = true }
+[target."cfg(unix)".build-dependencies]
+winapi = { version = "1", optional = true }
+## Unix
+[target."cfg(unix)".dependencies.libc]
+version = "1"
+optional = true
+        "#;
+        let args = Args { annotate_target: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** —  Foo\n* **`everywhere`** —  Everywhere\n\
+             * **`winapi`** *(only for `cfg(windows)` or `cfg(unix)`)* —  Windows\n\
+             * **`libc`** *(only for `cfg(unix)`)* —  Unix\n"
+        );
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert!(!parsed.contains("only for"), "{}", parsed);
+    }
+
+    #[test]
+    fn require_default_docs() {
+        let toml = r#"
+[features]
+default = ["foo", "bar", "dep:dep", "baz", "other/feat"]
+## Foo
+foo = []
+bar = []
+baz = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** *(enabled by default)* —  Foo\n* **`dep`** —  Dep\n");
+        let args = Args { require_default_docs: true, ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Default features without documentation: `bar`, `baz`"
+        );
+        let toml = toml.replace("bar = []\nbaz = []", "## Bar\nbar = []\n## Baz\nbaz = []");
+        assert!(process_toml(&toml, &args).is_ok());
+    }
+
+    #[test]
+    fn require_all_documented() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+bar = []
+##
+baz = []
+internal = ["bar"]
+[dependencies]
+dep = { version = "1", optional = true }
+        "#;
+        let args = Args { require_all_documented: true, ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Features without documentation: `bar`, `internal`"
+        );
+        let toml = toml
+            .replace("bar = []", "## Bar\nbar = []")
+            .replace("internal", "## Internal\ninternal");
+        assert!(process_toml(&toml, &args).is_ok());
+    }
+
+    #[test]
+    fn normalize_case() {
+        let toml = r#"
+[features]
+## enable the foo feature
+## on two lines
+foo = []
+## `bar` is already fine.
+bar = []
+## ébauche!
+baz = []
+## example:
+## ```
+## let x = 1;
+## ```
+qux = []
+        "#;
+        let args = Args { normalize_case: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** —  Enable the foo feature\n  on two lines.\n\
+             * **`bar`** —  `bar` is already fine.\n\
+             * **`baz`** —  Ébauche!\n\
+             * **`qux`** —  Example:\n  ```\n  let x = 1;\n  ```\n"
+        );
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert!(parsed.starts_with("* **`foo`** —  enable the foo feature\n  on two lines\n"));
+    }
+
+    #[test]
+    fn generated_note() {
+        let toml = r#"
+[features]
+## Foo
+foo = []
+#! The end
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n\n The end\n");
+        let args =
+            Args { generated_note: Some(super::GENERATED_NOTE.into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** —  Foo\n\n The end\n\n_Feature documentation generated from Cargo.toml._\n"
+        );
+        let args = Args { generated_note: Some("Do not edit".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n\n The end\n\n_Do not edit_\n");
+    }
+
+    #[test]
+    fn placeholders_in_values() {
+        // Templated manifests: only the names of the features matter, not the values
+        let toml = r#"
+[features]
+default = ["${DEFAULT_FEATURE}"]
+## Foo
+foo = ["${PLACEHOLDER}", "dep:{{name}}"]
+## Bar
+bar = [${UNQUOTED}, @OTHER@]
+## Baz
+baz = [
+    "${MULTI}", # comment with {
+    ${LINE},
+]
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n* **`bar`** —  Bar\n* **`baz`** —  Baz\n");
+    }
+
+    #[test]
+    fn icon() {
+        let toml = r#"
+[features]
+default = ["secure"]
+## Enable the encryption
+## @icon: 🔒
+## with a second line
+secure = []
+## No icon
+plain = []
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* 🔒 **`secure`** *(enabled by default)* —  Enable the encryption\n  with a second line\n\
+             * **`plain`** —  No icon\n"
+        );
+        let args = Args { default_marker_position: MarkerPosition::Prefix, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert!(parsed.starts_with("* *(enabled by default)* 🔒 **`secure`** —  "), "{}", parsed);
+        let toml = toml.replace("## with", "## @icon: 🔑\n## with");
+        assert_eq!(
+            process_toml(&toml, &Args::default()).unwrap_err(),
+            "Feature secure has more than one @icon tag"
+        );
+    }
+
+    #[test]
+    fn default_with_comments_on_each_line() {
+        let toml = r#"
+[features]
+default = [
+ "a", # comment a
+ "b", # comment b, with "quotes" and [brackets]
+]
+## A
+a = []
+## B
+b = []
+## C
+c = []
+        "#;
+        let parsed = parse_toml(toml, &Args::default()).unwrap();
+        let mut default_features = parsed.default_features.into_iter().collect::<Vec<_>>();
+        default_features.sort();
+        assert_eq!(default_features, ["a", "b"]);
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`a`** *(enabled by default)* —  A\n* **`b`** *(enabled by default)* —  B\n\
+             * **`c`** —  C\n"
+        );
+    }
+
+    #[test]
+    fn numeric_and_boolean_names() {
+        let toml = r#"
+[features]
+default = ["1", "true"]
+## One
+"1" = []
+## True
+"true" = ["1"]
+## Bare
+2 = []
+## False
+false = []
+## Single quotes
+'3' = ["false"]
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`1`** *(enabled by default)* —  One\n* **`true`** *(enabled by default)* —  True\n\
+             * **`2`** —  Bare\n* **`false`** —  False\n* **`3`** —  Single quotes\n"
+        );
+    }
+
+    #[test]
+    fn requirements_summary() {
+        let toml = r#"
+[features]
+## Foo
+## @requires: nightly
+foo = []
+## Bar
+## @platform: unix
+## @msrv: 1.70
+bar = []
+## Baz
+## @requires: nightly, alloc
+## @stability: experimental
+baz = []
+## Qux
+qux = []
+        "#;
+        let args = Args { requirements_summary: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "Some features require: nightly, unix, Rust 1.70, alloc.\n\n\
+             * **`foo`** —  Foo\n* **`bar`** —  Bar\n* **`baz`** *(experimental)* —  Baz\n\
+             * **`qux`** —  Qux\n"
+        );
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert!(parsed.starts_with("* **`foo`** —  Foo\n"), "{}", parsed);
+        let args = Args { prefix_filter: Some("q".into()), ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`qux`** —  Qux\n");
+    }
+
+    #[test]
+    fn tiers() {
+        let toml = r#"
+[package.metadata.document-features.tiers]
+free = ["std"]
+pro = ["enterprise", "dep:dep"]
+[features]
+## Std
+std = []
+## Enterprise
+enterprise = ["std", "audit"]
+## Audit
+audit = []
+## Nowhere
+nowhere = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+        "#;
+        let args = Args { format: Format::Tiers, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "| Feature | free | pro |\n|---|:---:|:---:|\n\
+             | **`std`** | ✓ | ✓ |\n\
+             | **`enterprise`** | ✗ | ✓ |\n\
+             | **`audit`** | ✗ | ✓ |\n\
+             | **`nowhere`** | ✗ | ✗ |\n\
+             | **`dep`** | ✗ | ✓ |\n"
+        );
+        let toml = toml.replace("[package.metadata.document-features.tiers]", "[other]");
+        assert_eq!(
+            process_toml(&toml, &args).unwrap_err(),
+            "No tiers in [package.metadata.document-features.tiers]"
+        );
+    }
+
+    #[test]
+    fn hide_footers() {
+        let toml = r#"
+[features]
+#! Header
+## Foo
+foo = []
+#! Footer
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(parsed, " Header\n* **`foo`** —  Foo\n\n Footer\n");
+        let args = Args { hide_footers: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, " Header\n* **`foo`** —  Foo\n");
+    }
+
+    #[test]
+    fn enables_item() {
+        let toml = r#"
+[features]
+## Foo
+## @enables-item: crate::foo::Bar
+foo = []
+## Baz
+## @enables-item: crate::baz, crate::Qux
+## @enables-item: crate::Quux::new
+baz = []
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`foo`** —  Foo\n\n  Enables: [`crate::foo::Bar`]\n\
+             * **`baz`** —  Baz\n\n  Enables: [`crate::baz`], [`crate::Qux`], [`crate::Quux::new`]\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_default() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+## Bar
+bar = []
+default = ["bar"]
+        "#;
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap_err(),
+            "The `default` feature is defined more than once"
+        );
+        let args = Args { last_default: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n* **`bar`** *(enabled by default)* —  Bar\n");
+        let args = Args { format: Format::Mermaid, ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed.matches("[\"default\"]").count(), 1, "{}", parsed);
+    }
+
+    #[test]
+    fn layout() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+## @requires: nightly
+foo = []
+#! Footer
+        "#;
+        let args = Args {
+            requirements_summary: true,
+            no_default_note: true,
+            generated_note: Some("Generated".into()),
+            ..Default::default()
+        };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "Some features require: nightly.\n\n\
+             Disabling default features turns off: `foo`.\n\n\
+             * **`foo`** *(enabled by default)* —  Foo\n\n Footer\n\n_Generated_\n"
+        );
+        let args = Args {
+            layout: Some(vec![
+                Section::GeneratedNote,
+                Section::Features,
+                Section::Summary,
+                Section::Footers,
+            ]),
+            ..args
+        };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "\n_Generated_\n* **`foo`** *(enabled by default)* —  Foo\n\
+             Some features require: nightly.\n\n\n Footer\n"
+        );
+        let args = Args { layout: Some(vec![Section::Features]), ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** *(enabled by default)* —  Foo\n");
+    }
+
+    #[test]
+    fn optional_dependency_without_version() {
+        let toml = r#"
+[dependencies]
+## Path
+by-path = { path = "../foo", optional = true }
+## Git
+by-git = { git = "https://example.com/foo.git", branch = "main", optional = true }
+## Table
+[dependencies.in-table]
+path = "../bar"
+optional = true
+        "#;
+        let parsed = process_toml(toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`by-path`** —  Path\n* **`by-git`** —  Git\n* **`in-table`** —  Table\n"
+        );
+        let toml = "[dependencies]\n## Path\nby-path = { path = \"../foo\" }\n";
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap_err(),
+            "Dependency by-path is not an optional dependency"
+        );
+    }
+
+    #[test]
+    fn text_format() {
+        let toml = r#"
+[abcd]
+[features]#xyz
+#! abc
+#
+###
+#! def
+#!
+## 123
+## 456
+feat1 = ["plop"]
+#! ghi
+no_doc = []
+##
+feat2 = ["momo"]
+#! klm
+default = ["feat1", "something_else"]
+#! end
+        "#;
+        let args = Args { format: Format::Text, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "abc\ndef\n\nfeat1 (default) - 123 456\n\nghi\nfeat2\n\nklm\nend\n");
+
+        let toml = r#"
+[features]
+#! ### Main features
+## The **foo** feature enables `foo()`
+##
+## Second paragraph
+foo = []
+## Bar
+## @stability: experimental
+bar = []
+        "#;
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "MAIN FEATURES\nfoo - The foo feature enables foo() Second paragraph\n\
+             bar (experimental) - Bar\n"
+        );
+
+        let toml = r#"
+[features]
+#! #### The *Main* [features](https://example.com)
+#! #[cfg(feature = "foo")] is not a heading
+## Use *this* or _that_, see [the docs](https://docs.rs) and [`Foo`] or [the item][Foo]
+## `snake_case_name`, foo_bar, 2 * 3 and *(experimental)*
+foo = []
+        "#;
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "THE MAIN FEATURES\n#[cfg(feature = \"foo\")] is not a heading\n\
+             foo - Use this or that, see the docs and Foo or the item \
+             snake_case_name, foo_bar, 2 * 3 and (experimental)\n"
+        );
+    }
+
+    #[test]
+    fn default_label() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+## Bar
+bar = []
+        "#;
+        let args = Args { default_label: Some("🔧 default".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** 🔧 default —  Foo\n* **`bar`** —  Bar\n");
+        let args = Args { default_marker_emphasis: Some(Emphasis::Bold), ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** **🔧 default** —  Foo\n* **`bar`** —  Bar\n");
+        let args = Args { default_marker_position: MarkerPosition::Prefix, ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **🔧 default** **`foo`** —  Foo\n* **`bar`** —  Bar\n");
+    }
+
+    #[test]
+    fn indent_dependents() {
+        let toml = r#"
+[features]
+## Basic
+basic = []
+## Advanced
+## on two lines
+advanced = ["basic"]
+## Expert
+expert = ["advanced", "dep:dep"]
+## Other
+other = ["basic", "undocumented"]
+## Cycle a
+a = ["b"]
+## Cycle b
+b = ["a"]
+#! Group
+## Grouped
+grouped = ["basic"]
+undocumented = []
+[dependencies]
+## Dep
+dep = { version = "1", optional = true }
+        "#;
+        let args = Args { indent_dependents: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`basic`** —  Basic\n\
+             \x20 * **`advanced`** —  Advanced\n    on two lines\n\
+             \x20   * **`expert`** —  Expert\n\
+             \x20 * **`other`** —  Other\n\
+             * **`b`** —  Cycle b\n\
+             \x20 * **`a`** —  Cycle a\n\
+             \n Group\n\
+             * **`grouped`** —  Grouped\n\
+             * **`dep`** —  Dep\n"
+        );
+        // A longer cycle is only broken once
+        let toml = "[features]\n## X\nx = [\"y\"]\n## Y\ny = [\"z\"]\n## Z\nz = [\"x\"]\n";
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`z`** —  Z\n  * **`y`** —  Y\n    * **`x`** —  X\n"
+        );
+    }
+
+    #[test]
+    fn hide_default() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+## Bar
+bar = []
+        "#;
+        let args = Args { hide_default: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* **`foo`** —  Foo\n* **`bar`** —  Bar\n");
+        let args = Args { format: Format::Text, ..args };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "foo - Foo\nbar - Bar\n");
+        // The note is not the marker
+        let args = Args { hide_default: true, no_default_note: true, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(
+            parsed,
+            "Disabling default features turns off: `foo`.\n\n* **`foo`** —  Foo\n* **`bar`** —  Bar\n"
+        );
+    }
+
+    #[test]
+    fn metadata_defaults() {
+        let toml = r#"
+[package.metadata.document-features]
+sort = "stability" # the unstable features go last
+feature_label = "<b>#{feature}</b>"
+[features]
+## @stability: experimental
+new = []
+## Stable
+old = []
+"#;
+        let mut args = Args::default();
+        super::apply_metadata(&mut args, toml).unwrap();
+        assert_eq!(args.sort, Sort::Stability);
+        assert_eq!(args.feature_label.as_deref(), Some("<b>#{feature}</b>"));
+        let stability = process_toml(toml, &args).unwrap();
+        assert!(stability.find("<b>#old</b>").unwrap() < stability.find("<b>#new</b>").unwrap());
+
+        // the arguments of the macro override the defaults
+        let declaration = super::Value::String("declaration".into());
+        assert!(super::apply_arg(&mut args, "sort", Some(&declaration)).is_ok());
+        assert_eq!(args.sort, Sort::Declaration);
+        assert!(super::apply_arg(&mut args, "sort", Some(&super::Value::Bool(true))).is_err());
+    }
+
+    #[test]
+    fn metadata_errors() {
+        let toml = "[package.metadata.document-features]\nunknown = true\n";
+        assert_eq!(
+            super::apply_metadata(&mut Args::default(), toml).err().unwrap(),
+            "Unknown argument `unknown` in [package.metadata.document-features]"
+        );
+        let toml = "[package.metadata.document-features]\nsort = 'random'\n";
+        assert_eq!(
+            super::apply_metadata(&mut Args::default(), toml).err().unwrap(),
+            "Invalid `sort` in [package.metadata.document-features]: \
+             expected \"declaration\", \"stability\" or \"alphabetical\""
+        );
+        // the profiles are not arguments
+        let toml = "[package.metadata.document-features.profiles]\nminimal = [\"std\"]\n";
+        assert!(super::apply_metadata(&mut Args::default(), toml).is_ok());
+
+        // comments in the table are not arguments, nor features
+        let toml = "[package.metadata.document-features]\n# The label of the features\n\
+                    feature_label = \"`{feature}`\" ## not a doc\n[features]\n## Foo\nfoo = []\n";
+        let mut args = Args::default();
+        super::apply_metadata(&mut args, toml).unwrap();
+        assert_eq!(args.feature_label.as_deref(), Some("`{feature}`"));
+        assert_eq!(process_toml(toml, &args).unwrap(), "* `foo` —  Foo\n");
+    }
+
+    #[test]
+    fn metadata_values() {
+        use super::Value;
+        let toml = r#"
+[package.metadata.document-features]
+layout = [
+    "features", # the list
+    'footers',
+]
+max_description_chars = 1_000
+default_label = "\"on\"\\"
+"#;
+        let values = super::metadata_args(toml).unwrap();
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "layout",
+                    Value::Array(vec![
+                        Value::String("features".into()),
+                        Value::String("footers".into())
+                    ])
+                ),
+                ("max_description_chars", Value::Integer(1000)),
+                ("default_label", Value::String("\"on\"\\".into())),
+            ]
+        );
+        assert_eq!(Value::from_toml("1.5"), Value::Other);
+        assert_eq!(Value::from_toml("[]"), Value::Array(vec![]));
+    }
+
+    #[test]
+    fn template() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo {default}
+## on two lines
+foo = []
+## @stability: experimental
+## Bar
+bar = []
+"#;
+        let args =
+            Args { template: Some("- `{feature}`{default}: {doc}".into()), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "- `foo` *(enabled by default)*: Foo {default}\n  on two lines\n- `bar`: Bar\n"
+        );
+        let args = Args {
+            template: Some("{feature}{default}".into()),
+            default_label: Some("[on]".into()),
+            ..Args::default()
+        };
+        assert_eq!(process_toml(toml, &args).unwrap(), "foo [on]\nbar\n");
+        let args = Args {
+            template: Some("{feature}{default}".into()),
+            hide_default: true,
+            ..Args::default()
+        };
+        assert_eq!(process_toml(toml, &args).unwrap(), "foo\nbar\n");
+    }
+
+    #[test]
+    fn line_template() {
+        let toml = "[features]\ndefault = [\"foo\"]\n## Foo\nfoo = []\n## Bar\nbar = []\n";
+        let args = Args {
+            line_template: Some("- {label}{default} — {doc} ({name})".into()),
+            template: Some("{feature}".into()),
+            bullet: Some("+".into()),
+            feature_label: Some("<code>{feature}</code>".into()),
+            default_feature_label: Some("<b>{feature}</b>".into()),
+            ..Args::default()
+        };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "- <b>foo</b> *(enabled by default)* — Foo (foo)\n- <code>bar</code> — Bar (bar)\n"
+        );
+        let mut args = Args::default();
+        let value = super::Value::String("{feature}: {doc}".into());
+        assert!(super::apply_arg(&mut args, "line_template", Some(&value)).is_err());
+    }
+
+    #[test]
+    fn bullet() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+bar = []
+## Baz
+baz = []
+"#;
+        let args = Args { bullet: Some("-".into()), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "- **`foo`** *(enabled by default)* —  Foo\n- **`baz`** —  Baz\n"
+        );
+        assert_eq!(
+            process_toml("[features]\n## Foo\n## \nfoo = []\n## \nbar = []\n", &args).unwrap(),
+            "- **`foo`** —  Foo\n- **`bar`**\n"
+        );
+    }
+
+    #[test]
+    fn description_words() {
+        let toml = r#"
+[features]
+## Enable foo
+foo = []
+## Enable the bar feature, which is
+## documented on two lines
+bar = []
+## Baz
+baz = []
+"#;
+        let args = Args { min_description_words: Some(2), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Descriptions with fewer than 2 words: `baz` (1)"
+        );
+        let args = Args { max_description_words: Some(5), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Descriptions with more than 5 words: `bar` (10)"
+        );
+        let args = Args {
+            min_description_words: Some(1),
+            max_description_words: Some(10),
+            ..Args::default()
+        };
+        assert!(process_toml(toml, &args).is_ok());
+    }
+
+    #[test]
+    fn implicit_features_note() {
+        let toml = r#"
+[dependencies]
+## Serialization support
+serde = { version = "1.0", optional = true }
+"#;
+        let args = Args {
+            implicit_features_note: Some(super::IMPLICIT_FEATURES_NOTE.into()),
+            ..Args::default()
+        };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "This crate has no Cargo features; the following optional dependencies act as \
+             implicit features.\n\n* **`serde`** —  Serialization support\n"
+        );
+        let args = Args { implicit_features_note: Some("Implicit:".into()), ..Args::default() };
+        assert_eq!(
+            process_toml(&format!("[features]\n{}", toml), &args).unwrap(),
+            "Implicit:\n\n* **`serde`** —  Serialization support\n"
+        );
+        // no note when there are documented features
+        let toml = format!("[features]\n## Foo\nfoo = []\n{}", toml);
+        assert_eq!(
+            process_toml(&toml, &args).unwrap(),
+            "* **`foo`** —  Foo\n* **`serde`** —  Serialization support\n"
+        );
+    }
+
+    #[test]
+    fn separator() {
+        let toml = r#"
+[features]
+## Foo
+## on two lines
+foo = []
+##
+bar = []
+"#;
+        let args = Args { separator: Some(":".into()), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`foo`**: Foo\n  on two lines\n* **`bar`**\n"
+        );
+        let args = Args { separator: Some(" -".into()), join: Join::Space, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`foo`** - Foo on two lines\n* **`bar`**\n"
+        );
+    }
+
+    #[test]
+    fn format_html() {
+        let toml = r#"
+[features]
+default = ["foo"]
+#! ## Main features
+#! Some <intro>
+#! on two lines
+## Foo & "bar"
+## on two lines
+foo = []
+## @stability: experimental
+## Bar
+bar = []
+#! ### Other
+## Baz
+baz = []
+#! Footer
+"#;
+        let args = Args { format: Format::Html, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "<h2>Main features</h2>\n<p>Some &lt;intro&gt; on two lines</p>\n<ul>\n\
+             <li><code>foo</code> <em>(enabled by default)</em> — Foo &amp; &quot;bar&quot; on two lines</li>\n\
+             <li><code>bar</code> <em>(experimental)</em> — Bar</li>\n\
+             </ul>\n<h3>Other</h3>\n<ul>\n<li><code>baz</code> — Baz</li>\n</ul>\n<p>Footer</p>\n"
+        );
+        let args = Args {
+            format: Format::Html,
+            default_label: Some("default".into()),
+            generated_note: Some("Generated".into()),
+            hide_footers: true,
+            ..Args::default()
+        };
+        assert_eq!(
+            process_toml("[features]\ndefault = [\"foo\"]\n## Foo\nfoo = []\n#! Footer", &args)
+                .unwrap(),
+            "<ul>\n<li><code>foo</code> <em>default</em> — Foo</li>\n</ul>\n<p><em>Generated</em></p>\n"
+        );
+    }
+
+    #[test]
+    fn default_feature_label() {
+        let toml = "[features]\ndefault = [\"foo\"]\n## Foo\nfoo = []\n## Bar\nbar = []\n";
+        let args = Args {
+            feature_label: Some("`{feature}`".into()),
+            default_feature_label: Some("<b>{feature}</b>".into()),
+            hide_default: true,
+            ..Args::default()
+        };
+        assert_eq!(process_toml(toml, &args).unwrap(), "* <b>foo</b> —  Foo\n* `bar` —  Bar\n");
+        // falls back to the default label
+        let args = Args { default_feature_label: Some("**{feature}**".into()), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **foo** *(enabled by default)* —  Foo\n* **`bar`** —  Bar\n"
+        );
+    }
+
+    #[test]
+    fn format_table() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo | bar
+## on two lines
+foo = []
+#! ### Other
+## @stability: deprecated
+## Baz
+baz = []
+#! Footer
+"#;
+        let args = Args { format: Format::Table, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "| Feature | Default | Description |\n|---|:---:|---|\n\
+             | **`foo`** | ✓ | Foo \\| bar on two lines |\n\
+             \n ### Other\n\n\
+             | Feature | Default | Description |\n|---|:---:|---|\n\
+             | **`baz`** *(deprecated)* |  | Baz |\n\
+             \n Footer\n"
+        );
+    }
+
+    #[test]
+    fn trailing_comma_in_default() {
+        let toml = r#"
+[features]
+default = [
+    "a",
+    "b", # last
+]
+## A
+a = []
+## B
+b = []
+"#;
+        let parsed = parse_toml(toml, &Args::default()).unwrap();
+        let mut defaults = parsed.default_features.iter().map(String::as_str).collect::<Vec<_>>();
+        defaults.sort_unstable();
+        assert_eq!(defaults, ["a", "b"]);
+        let (_, default) = parsed.enables.iter().find(|(f, _)| *f == "default").unwrap();
+        assert_eq!(default, &["a", "b"]);
+    }
+
+    #[test]
+    fn format_dl() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo <1>
+foo = []
+#! Optional dependencies
+[dependencies]
+## Bar
+bar = { version = "1", optional = true }
+"#;
+        let args = Args { format: Format::Dl, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "<dl>\n<dt><code>foo</code> <em>(enabled by default)</em></dt><dd>Foo &lt;1&gt;</dd>\n\
+             </dl>\n<p>Optional dependencies</p>\n<dl>\n<dt><code>bar</code></dt><dd>Bar</dd>\n</dl>\n"
+        );
+    }
+
+    #[test]
+    fn note_no_defaults() {
+        let args = Args { note_no_defaults: true, ..Args::default() };
+        assert_eq!(
+            process_toml("[features]\ndefault = []\n## Foo\nfoo = []\n", &args).unwrap(),
+            "_No features are enabled by default._\n\n* **`foo`** —  Foo\n"
+        );
+        assert_eq!(
+            process_toml("[features]\ndefault = [\"foo\"]\n## Foo\nfoo = []\n", &args).unwrap(),
+            "* **`foo`** *(enabled by default)* —  Foo\n"
+        );
+        let args = Args { format: Format::Html, ..args };
+        assert_eq!(
+            process_toml("[features]\n## Foo\nfoo = []\n", &args).unwrap(),
+            "<p><em>No features are enabled by default.</em></p>\n<ul>\n<li><code>foo</code> — Foo</li>\n</ul>\n"
+        );
     }
-    process_toml(&code, &Args::default()).map_or_else(
-        |e| error(&e),
-        |r| std::iter::once(proc_macro::TokenTree::from(proc_macro::Literal::string(&r))).collect(),
-    )
-}
 
-#[cfg(feature = "self-test")]
-macro_rules! self_test {
-    (#[doc = $toml:literal] => #[doc = $md:literal]) => {
-        concat!(
-            "\n`````rust\n\
-            fn normalize_md(md : &str) -> String {
-               md.lines().skip_while(|l| l.is_empty()).map(|l| l.trim())
-                .collect::<Vec<_>>().join(\"\\n\")
-            }
-            assert_eq!(normalize_md(document_features::self_test_helper!(",
-            stringify!($toml),
-            ")), normalize_md(",
-            stringify!($md),
-            "));\n`````\n\n"
-        )
-    };
-}
+    #[test]
+    fn format_rst() {
+        let toml = r#"
+[features]
+default = ["foo"]
+#! ## Main features
+#! Introduction
+## Foo
+##
+## second paragraph
+foo = []
+## @stability: experimental
+bar = []
+#! ### Other
+## Baz
+baz = []
+#! Footer
+"#;
+        let args = Args { format: Format::Rst, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "Main features\n-------------\n\nIntroduction\n\n\
+             * ``foo`` *(enabled by default)* — Foo\n\n  second paragraph\n\
+             * ``bar`` *(experimental)*\n\
+             \nOther\n~~~~~\n\n\
+             * ``baz`` — Baz\n\
+             \nFooter\n"
+        );
+    }
 
-#[cfg(not(feature = "self-test"))]
-macro_rules! self_test {
-    (#[doc = $toml:literal] => #[doc = $md:literal]) => {
-        concat!(
-            "This contents in Cargo.toml:\n`````toml",
-            $toml,
-            "\n`````\n Generates the following:\n\
-            <table><tr><th>Preview</th></tr><tr><td>\n\n",
-            $md,
-            "\n</td></tr></table>\n\n&nbsp;\n",
-        )
-    };
-}
+    #[test]
+    fn data_attributes() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+## @since: 1.2
+foo = []
+[dependencies]
+## Bar
+bar = { version = "1", optional = true }
+"#;
+        let args = Args { format: Format::Html, data_attributes: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "<ul>\n\
+             <li data-default=\"true\" data-optional-dep=\"false\" data-since=\"1.2\">\
+             <code>foo</code> <em>(enabled by default)</em> — Foo</li>\n\
+             <li data-default=\"false\" data-optional-dep=\"true\"><code>bar</code> — Bar</li>\n\
+             </ul>\n"
+        );
+        let args = Args { format: Format::Dl, ..args };
+        assert!(process_toml(toml, &args).unwrap().contains(
+            "<dt data-default=\"false\" data-optional-dep=\"true\"><code>bar</code></dt>"
+        ));
+    }
 
-use self_test;
+    #[test]
+    fn format_support() {
+        let check = |args: Args| super::check_format_support(&args);
+        assert!(check(Args { normalize_case: true, ..Args::default() }).is_ok());
+        assert_eq!(
+            check(Args { format: Format::Html, normalize_case: true, ..Args::default() }),
+            Err("`normalize_case` is only supported with format = \"markdown\"".into())
+        );
+        assert_eq!(
+            check(Args {
+                format: Format::Text,
+                default_label: Some("on".into()),
+                ..Args::default()
+            }),
+            Err("`default_label` is only supported with format = \"markdown\", \"html\", \
+                 \"dl\" or \"rst\""
+                .into())
+        );
+        assert!(check(Args { format: Format::Dl, accessible: true, ..Args::default() }).is_ok());
+        // The HTML formats always write the name of the feature in `<code>`
+        let label = Some("**{feature}**".to_string());
+        for format in &[Format::Html, Format::Dl] {
+            let args = Args { format: *format, feature_label: label.clone(), ..Args::default() };
+            assert!(check(args).unwrap_err().starts_with("`feature_label` is only supported"));
+            let args =
+                Args { format: *format, default_feature_label: label.clone(), ..Args::default() };
+            assert!(check(args).unwrap_err().starts_with("`default_feature_label` is only"));
+        }
+        let args = Args { format: Format::Table, default_feature_label: label, ..Args::default() };
+        assert!(check(args).is_ok());
+        for format in &[Format::Rst, Format::Text] {
+            let args = Args { format: *format, toc: true, ..Args::default() };
+            assert_eq!(
+                check(args),
+                Err("`toc` is only supported with format = \"markdown\", \"table\", \"html\" \
+                     or \"dl\""
+                    .into())
+            );
+            let args = Args { format: *format, anchors: true, ..Args::default() };
+            assert!(check(args).unwrap_err().starts_with("`anchors` is only supported"));
+        }
+        let args = Args { format: Format::Html, legend: true, ..Args::default() };
+        assert_eq!(
+            check(args),
+            Err("`legend` is only supported with format = \"markdown\"".into())
+        );
+        let args = Args { format: Format::Table, cross_link: true, ..Args::default() };
+        assert!(check(args).is_ok());
+        let args = Args { format: Format::Dl, cross_link: true, ..Args::default() };
+        assert!(check(args).unwrap_err().starts_with("`cross_link` is only supported"));
+        let include = Some(vec!["tls-*".to_string()]);
+        let args = Args { format: Format::Badges, include: include.clone(), ..Args::default() };
+        assert!(check(args).is_ok());
+        let args = Args { format: Format::Mermaid, include, ..Args::default() };
+        assert!(check(args).unwrap_err().starts_with("`include` is only supported"));
+        let args = Args { format: Format::Mermaid, profile: Some("x".into()), ..Args::default() };
+        assert!(check(args).unwrap_err().starts_with("`profile` is only supported"));
+        let args = Args { format: Format::Table, indent_dependents: true, ..Args::default() };
+        assert!(check(args).unwrap_err().starts_with("`indent_dependents` is only supported"));
+        let args = Args { format: Format::Mermaid, badge_url: Some("x".into()), ..Args::default() };
+        assert!(check(args).unwrap_err().starts_with("`badge_url` is only supported"));
+    }
 
-// The following struct is inserted only during generation of the documentation in order to exploit doc-tests.
-// These doc-tests are used to check that invalid arguments to the `document_features!` macro cause a compile time error.
-// For a more principled way of testing compilation error, maybe investigate <https://docs.rs/trybuild>.
-//
-/// ```rust
-/// #![doc = document_features::document_features!()]
-/// #![doc = document_features::document_features!(feature_label = "**`{feature}`**")]
-/// #![doc = document_features::document_features!(feature_label = r"**`{feature}`**")]
-/// #![doc = document_features::document_features!(feature_label = r#"**`{feature}`**"#)]
-/// #![doc = document_features::document_features!(feature_label = "<span class=\"stab portability\"><code>{feature}</code></span>")]
-/// #![doc = document_features::document_features!(feature_label = r#"<span class="stab portability"><code>{feature}</code></span>"#)]
-/// ```
-/// ```compile_fail
-/// #![doc = document_features::document_features!(feature_label > "<span>{feature}</span>")]
-/// ```
-/// ```compile_fail
-/// #![doc = document_features::document_features!(label = "<span>{feature}</span>")]
-/// ```
-/// ```compile_fail
-/// #![doc = document_features::document_features!(feature_label = "{feat}")]
-/// ```
-/// ```compile_fail
-/// #![doc = document_features::document_features!(feature_label = 3.14)]
-/// ```
-/// ```compile_fail
-/// #![doc = document_features::document_features!(feature_label = )]
-/// ```
-/// ```compile_fail
-/// #![doc = document_features::document_features!(feature_label = "**`{feature}`**" extra)]
-/// ```
-#[cfg(doc)]
-struct FeatureLabelCompilationTest;
+    #[test]
+    fn accessible() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+#! Others
+## Bar
+bar = []
+"#;
+        let args = Args { format: Format::Html, accessible: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "<ul role=\"list\">\n\
+             <li><code>foo</code> <em aria-label=\"enabled by default\">(enabled by default)</em> \
+             — Foo</li>\n</ul>\n<p>Others</p>\n\
+             <ul role=\"list\">\n<li><code>bar</code> — Bar</li>\n</ul>\n"
+        );
+        let args = Args { format: Format::Dl, ..args };
+        assert!(process_toml(toml, &args)
+            .unwrap()
+            .starts_with("<dl>\n<dt><code>foo</code> <em aria"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::{process_toml, Args};
+    #[test]
+    fn toc() {
+        let toml = r#"
+[features]
+## Foo
+foo = []
+## Foo bar
+"foo.bar" = []
+## Other
+Foo-Bar = []
+"#;
+        let args = Args { toc: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* [`foo`](#feature-foo)\n* [`foo.bar`](#feature-foo-bar)\n\
+             * [`Foo-Bar`](#feature-foo-bar-2)\n\n\
+             * <a id=\"feature-foo\"></a>**`foo`** —  Foo\n\
+             * <a id=\"feature-foo-bar\"></a>**`foo.bar`** —  Foo bar\n\
+             * <a id=\"feature-foo-bar-2\"></a>**`Foo-Bar`** —  Other\n"
+        );
+        let args = Args { format: Format::Html, ..args };
+        assert_eq!(
+            process_toml("[features]\n## Foo\nfoo = []\n", &args).unwrap(),
+            "<ul>\n<li><a href=\"#feature-foo\"><code>foo</code></a></li>\n</ul>\n\
+             <ul>\n<li id=\"feature-foo\"><code>foo</code> — Foo</li>\n</ul>\n"
+        );
+        let args = Args {
+            format: Format::Markdown,
+            layout: Some(vec![Section::Features, Section::Toc]),
+            ..args
+        };
+        assert_eq!(
+            process_toml("[features]\n## Foo\nfoo = []\n", &args).unwrap(),
+            "* <a id=\"feature-foo\"></a>**`foo`** —  Foo\n* [`foo`](#feature-foo)\n\n"
+        );
+        let args = Args { layout: Some(vec![Section::Features]), ..args };
+        assert_eq!(
+            process_toml("[features]\n## Foo\nfoo = []\n", &args).unwrap(),
+            "* <a id=\"feature-foo\"></a>**`foo`** —  Foo\n"
+        );
+    }
 
-    #[track_caller]
-    fn test_error(toml: &str, expected: &str) {
-        let err = process_toml(toml, &Args::default()).unwrap_err();
-        assert!(err.contains(expected), "{:?} does not contain {:?}", err, expected)
+    #[test]
+    fn toc_headings() {
+        // The same heading with different levels
+        let toml = r#"
+[features]
+#! ### Experimental
+#! Unstable features
+## Foo
+foo = []
+#! #### Experimental
+## Bar
+bar = []
+#! ## Other  stuff
+## Baz
+baz = []
+"#;
+        let args = Args { toc: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* [Experimental](#section-experimental)\n* [`foo`](#feature-foo)\n\
+             * [Experimental](#section-experimental-2)\n* [`bar`](#feature-bar)\n\
+             * [Other  stuff](#section-other--stuff)\n* [`baz`](#feature-baz)\n\n\
+             \x20### <a id=\"section-experimental\"></a>Experimental\n Unstable features\n\
+             * <a id=\"feature-foo\"></a>**`foo`** —  Foo\n\n\
+             \x20#### <a id=\"section-experimental-2\"></a>Experimental\n\
+             * <a id=\"feature-bar\"></a>**`bar`** —  Bar\n\n\
+             \x20## <a id=\"section-other--stuff\"></a>Other  stuff\n\
+             * <a id=\"feature-baz\"></a>**`baz`** —  Baz\n"
+        );
+        let args = Args { format: Format::Html, ..args };
+        let html = process_toml(toml, &args).unwrap();
+        assert!(html.contains("<li><a href=\"#section-experimental-2\">Experimental</a></li>"));
+        assert!(html.contains("<h4 id=\"section-experimental-2\">Experimental</h4>"), "{}", html);
+        // Without `toc`, the headings are left as they are
+        let args = Args { anchors: true, ..Args::default() };
+        assert!(!process_toml(toml, &args).unwrap().contains("section-"));
     }
 
     #[test]
-    fn only_get_balanced_in_correct_table() {
-        process_toml(
-            r#"
+    fn anchors() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+[dependencies]
+## Serde
+serde = { version = "1", optional = true }
+"#;
+        let args = Args { anchors: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* <a id=\"feature-foo\"></a>**`foo`** *(enabled by default)* —  Foo\n\
+             * <a id=\"feature-serde\"></a>**`serde`** —  Serde\n"
+        );
+        let args = Args { format: Format::Table, ..args };
+        assert!(process_toml(toml, &args)
+            .unwrap()
+            .contains("| <a id=\"feature-serde\"></a>**`serde`** |  | Serde |"));
+        assert!(!process_toml(toml, &Args::default()).unwrap().contains("<a id"));
+    }
 
-[package.metadata.release]
-pre-release-replacements = [
-  {test=\"\#\# \"},
-]
-[abcd]
-[features]#xyz
-#! abc
-#
-###
-#! def
-#!
-## 123
-## 456
-feat1 = ["plop"]
-#! ghi
-no_doc = []
-##
-feat2 = ["momo"]
-#! klm
-default = ["feat1", "something_else"]
-#! end
-            "#,
-            &Args::default(),
-        )
-        .unwrap();
+    #[test]
+    fn stray_characters_in_name() {
+        let toml =
+            "[features]\n## Foo\n\"foo \" = []\n## Bar\nbar\t\r = []\n## Baz\n'\u{7f}baz' = []\n";
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`foo`** —  Foo\n* **`bar`** —  Bar\n* **`baz`** —  Baz\n"
+        );
     }
 
     #[test]
-    fn no_features() {
-        let r = process_toml(
-            r#"
+    fn document_dev_deps() {
+        let toml = r#"
 [features]
+## Foo
+foo = []
+[dev-dependencies]
+## For the benchmarks
+criterion = { version = "0.5", optional = true }
 [dependencies]
-foo = 4;
-"#,
+## Serde
+serde = { version = "1", optional = true }
+"#;
+        let args = Args { document_dev_deps: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`foo`** —  Foo\n* **`serde`** —  Serde\n\
+             \n#### Development dependencies\n* **`criterion`** —  For the benchmarks\n"
+        );
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`foo`** —  Foo\n* **`criterion`** —  For the benchmarks\n* **`serde`** —  Serde\n"
+        );
+        let parsed = parse_toml(
+            "[dev-dependencies.a]\noptional = true\n[dependencies]\n## B\nb = { version = \"1\", optional = true }\n[dev-dependencies]\nb = \"1\"\n",
             &Args::default(),
         )
         .unwrap();
-        assert_eq!(r, "*No documented features in Cargo.toml*");
+        // `b` is also a regular dependency
+        assert!(!parsed.features[0].dev_dependency);
     }
 
     #[test]
-    fn no_features2() {
-        let r = process_toml(
-            r#"
-[packages]
-[dependencies]
-"#,
-            &Args::default(),
+    fn cross_link() {
+        let toml = r#"
+[features]
+## Enable TLS, see also `std` and `other`
+tls = []
+## Enabled by [`tls`](#tls) or `tls`
+std = []
+"#;
+        let args = Args { cross_link: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* <a id=\"feature-tls\"></a>**`tls`** —  Enable TLS, see also [`std`](#feature-std) \
+             and `other`\n\
+             * <a id=\"feature-std\"></a>**`std`** —  Enabled by [`tls`](#tls) or \
+             [`tls`](#feature-tls)\n"
+        );
+    }
+
+    #[test]
+    fn wrapper_class() {
+        let toml = "[features]\n## Foo\nfoo = []\n";
+        let args = Args {
+            format: Format::Html,
+            wrapper_class: Some("feature-list".into()),
+            ..Args::default()
+        };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "<div class=\"feature-list\">\n<ul>\n<li><code>foo</code> — Foo</li>\n</ul>\n</div>\n"
+        );
+        let args = Args { wrapper_class: Some(String::new()), ..args };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "<ul>\n<li><code>foo</code> — Foo</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn workspace() {
+        let root = TempDir::new("workspace");
+        for dir in &["crates/a", "crates/b", "crates/excluded", "undocumented", "other"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let cargo_toml = "[workspace]\nmembers = [\n  \"crates/*\", # all the crates\n  \
+                          \"undocumented\",\n  \"other\",\n]\nexclude = [\"crates/excluded\"]\n\
+                          resolver = \"2\"\n";
+        std::fs::write(
+            root.join("crates/a/Cargo.toml"),
+            "[package]\nname = \"crate-a\"\n[features]\n## Std in a\nstd = []\n",
         )
         .unwrap();
-        assert_eq!(r, "*No documented features in Cargo.toml*");
+        std::fs::write(
+            root.join("crates/b/Cargo.toml"),
+            "[package]\nname = \"crate-b\"\n[features]\n## Std in b\nstd = []\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("crates/excluded/Cargo.toml"), "[features]\n## Ex\nex = []\n")
+            .unwrap();
+        std::fs::write(root.join("undocumented/Cargo.toml"), "[features]\nfoo = []\n").unwrap();
+        std::fs::write(root.join("other/Cargo.toml"), "[features]\n## Bar\nbar = []\n").unwrap();
+
+        assert_eq!(
+            super::process_workspace(&root, cargo_toml, &Args::default()).unwrap(),
+            "### `crate-a`\n\n* **`std`** —  Std in a\n\n\
+             ### `crate-b`\n\n* **`std`** —  Std in b\n\n\
+             ### `other`\n\n* **`bar`** —  Bar\n"
+        );
+        let args = Args { format: Format::Html, ..Args::default() };
+        let html = super::process_workspace(&root, cargo_toml, &args).unwrap();
+        assert!(html.starts_with("<h3><code>crate-a</code></h3>\n<ul>\n"), "{}", html);
+        let args = Args { anchors: true, ..Args::default() };
+        let anchored = super::process_workspace(&root, cargo_toml, &args).unwrap();
+        assert!(anchored.contains("<a id=\"feature-crate-a-std\"></a>"), "{}", anchored);
+        assert!(anchored.contains("<a id=\"feature-crate-b-std\"></a>"), "{}", anchored);
+        assert!(anchored.contains("<a id=\"feature-other-bar\"></a>"), "{}", anchored);
+
+        let err = super::process_workspace(&root, "[package]\nname = \"x\"\n", &Args::default());
+        assert_eq!(err.unwrap_err(), "`workspace` requires a [workspace] table with `members`");
+        let err = super::process_workspace(
+            &root,
+            "[workspace]\nmembers = [\"other\", \"missing\"]\n",
+            &Args::default(),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Can't open "), "{}", err);
     }
 
     #[test]
-    fn parse_error3() {
-        test_error(
-            r#"
+    fn table_header_with_comment() {
+        let expected = "* **`foo`** —  Foo\n";
+        for header in &["[features] # comment", "[features]## not-a-doc", "[features]#! not-a-doc"]
+        {
+            let toml = format!("{}\n## Foo\nfoo = []\n", header);
+            assert_eq!(process_toml(&toml, &Args::default()).unwrap(), expected, "{}", header);
+        }
+        let toml = "[dependencies] #! stray\n## Bar\nbar = { version = \"1\", optional = true }\n\
+                    [features] ## not-a-doc\n## Foo\nfoo = []\n";
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`bar`** —  Bar\n* **`foo`** —  Foo\n"
+        );
+        let toml = "[package.metadata.document-features] # the arguments\nformat = \"html\"\n\
+                    [features]\n## Foo\nfoo = []\n";
+        let args = super::metadata_args(toml).unwrap();
+        assert_eq!(args, vec![("format", super::Value::String("html".into()))]);
+    }
+
+    #[test]
+    fn non_additive() {
+        let toml = r#"
 [features]
-ff = []
-[abcd
-efgh
-[dependencies]
-"#,
-            "Parse error while parsing line: [abcd",
+## Use the system allocator
+## @non-additive
+system-alloc = []
+## Foo
+foo = []
+#! Other
+## Bar
+bar = []
+"#;
+        let warning =
+            "\n\n  ⚠️ *This feature is not additive: it changes the behavior of the crate.*";
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            format!(
+                "* **`system-alloc`** —  Use the system allocator{}\n\
+                 * **`foo`** —  Foo\n\n Other\n* **`bar`** —  Bar\n",
+                warning
+            )
+        );
+        let args = Args { group_non_additive: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            format!(
+                "* **`foo`** —  Foo\n\n Other\n* **`bar`** —  Bar\n\
+                 \n#### Non-additive features (use with care)\n\
+                 * **`system-alloc`** —  Use the system allocator{}\n",
+                warning
+            )
         );
     }
 
     #[test]
-    fn parse_error4() {
-        test_error(
-            r#"
+    fn sort_alphabetical() {
+        let toml = r#"
 [features]
-## dd
-## ff
-#! ee
-## ff
-"#,
-            "Cannot mix",
+#! Intro
+## Zeta
+zeta = []
+## Beta
+beta = []
+## Alpha
+alpha = []
+#! Backends
+## Wgpu
+wgpu = []
+## Gl
+gl = []
+"#;
+        let args = Args { sort: Sort::Alphabetical, ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            " Intro\n* **`alpha`** —  Alpha\n* **`beta`** —  Beta\n* **`zeta`** —  Zeta\n\n\
+             \x20Backends\n* **`gl`** —  Gl\n* **`wgpu`** —  Wgpu\n"
+        );
+    }
+
+    #[test]
+    fn include() {
+        let toml = r#"
+[features]
+## Internal
+internal = []
+#! ### API
+## API v1
+api-v1 = []
+## API v2
+api-v2 = []
+#! ### Other
+## Std
+std = []
+## Private
+private-api = []
+        "#;
+        let args = Args { include: Some(vec!["api-*".into(), "std".into()]), ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            " ### API\n* **`api-v1`** —  API v1\n* **`api-v2`** —  API v2\n\n\
+             \x20### Other\n* **`std`** —  Std\n"
         );
+        let include = super::Value::String("api-v?, , *-api".into());
+        let mut args = Args::default();
+        assert!(super::apply_arg(&mut args, "include", Some(&include)).is_ok());
+        assert_eq!(args.include, Some(vec!["api-v?".into(), "*-api".into()]));
+        let empty = super::Value::String(" , ".into());
+        assert!(super::apply_arg(&mut args, "include", Some(&empty)).is_err());
+        let args = Args { include: Some(vec!["xyz*".into()]), ..Default::default() };
+        assert_eq!(process_toml(toml, &args).unwrap_err(), "No documented features match `xyz*`");
     }
 
     #[test]
-    fn parse_error5() {
-        test_error(
-            r#"
+    fn exclude() {
+        let toml = r#"
 [features]
-## dd
-"#,
-            "not associated with a feature",
+## Std
+std = []
+#! ### Internal
+## Internal
+internal-build = []
+## Hidden
+__hidden = []
+#! ### Other
+## Serde
+serde = []
+        "#;
+        let exclude = Some(vec!["internal-*".into(), "__*".into()]);
+        let args = Args { exclude, ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`std`** —  Std\n\n ### Other\n* **`serde`** —  Serde\n"
+        );
+        let args = Args { exclude: Some(vec!["*".into()]), ..Default::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "All the documented features are excluded by `*`"
         );
     }
 
     #[test]
-    fn parse_error6() {
-        test_error(
-            r#"
+    fn test_glob_match() {
+        use super::glob_match;
+        assert!(glob_match("api-*", "api-v1"));
+        assert!(glob_match("api-*", "api-"));
+        assert!(!glob_match("api-*", "private-api"));
+        assert!(glob_match("*-api", "private-api"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("v?", "v2"));
+        assert!(!glob_match("v?", "v10"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("std", "std"));
+        assert!(!glob_match("std", "no-std"));
+    }
+
+    #[test]
+    fn default_on() {
+        let toml = r#"
 [features]
-# ff
+## Use the native dialogs
+## @default-on: windows, macos
+native-dialogs = []
+## Foo
 foo = []
-default = [
-#ffff
-# ff
-"#,
-            "Parse error while parsing value default",
+"#;
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`native-dialogs`** —  Use the native dialogs\n\n  \
+             Enabled by default on: windows, macos\n\
+             * **`foo`** —  Foo\n"
         );
     }
 
     #[test]
-    fn parse_error7() {
-        test_error(
-            r#"
-[features]
-# f
-foo = [ x = { ]
-bar = []
-"#,
-            "Parse error while parsing value foo",
+    fn whitespace_only_comment() {
+        let toml = "[features]\n##   \n##\n##\t\n##    \nfoo = []\n## Bar\nbar = []\n";
+        assert_eq!(
+            process_toml(toml, &Args::default()).unwrap(),
+            "* **`foo`**\n* **`bar`** —  Bar\n"
+        );
+        let args = Args { format: Format::Table, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "| Feature | Default | Description |\n|---|:---:|---|\n\
+             | **`foo`** |  |  |\n| **`bar`** |  | Bar |\n"
         );
     }
 
     #[test]
-    fn not_a_feature1() {
-        test_error(
-            r#"
-## hallo
-[features]
-"#,
-            "Not a feature: `[features]`",
+    fn default_icon() {
+        let toml = "[features]\ndefault = [\"foo\"]\n## Foo\nfoo = []\n## Bar\nbar = []\n";
+        let args = Args { default_icon: Some("★".into()), ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* ★ **`foo`** *(enabled by default)* —  Foo\n* **`bar`** —  Bar\n"
+        );
+        let args = Args { default_marker_position: MarkerPosition::Prefix, ..args };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* ★ *(enabled by default)* **`foo`** —  Foo\n* **`bar`** —  Bar\n"
+        );
+        let args = Args { hide_default: true, ..args };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* ★ **`foo`** —  Foo\n* **`bar`** —  Bar\n"
         );
     }
 
     #[test]
-    fn not_a_feature2() {
-        test_error(
-            r#"
-[package]
-## hallo
+    fn include_undocumented() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
 foo = []
-"#,
-            "Comment cannot be associated with a feature: \"hallo\"",
+bar = []
+#! ### Group
+baz = []
+## Qux
+qux = []
+[dependencies]
+dep = { version = "1", optional = true }
+"#;
+        let args = Args { include_undocumented: Some(String::new()), ..Args::default() };
+        let expected = "* **`foo`** *(enabled by default)* —  Foo\n* **`bar`**\n\n\
+                        \x20### Group\n* **`baz`**\n* **`qux`** —  Qux\n";
+        assert_eq!(process_toml(toml, &args).unwrap(), expected);
+        let args = Args { include_undocumented: Some("*TODO*".into()), ..args };
+        let expected = expected.replace("**`bar`**", "**`bar`** —  *TODO*");
+        let expected = expected.replace("**`baz`**", "**`baz`** —  *TODO*");
+        assert_eq!(process_toml(toml, &args).unwrap(), expected);
+
+        // With the comments below the features
+        let toml = "[features]\ndefault = []\nfoo = []\n## Foo\nbar = []\nbaz = []\n";
+        let args = Args {
+            include_undocumented: Some(String::new()),
+            comment_position: CommentPosition::Below,
+            ..Args::default()
+        };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "* **`foo`** —  Foo\n* **`bar`**\n* **`baz`**\n"
+        );
+
+        // The placeholders don't count as documentation
+        let toml = "[features]\ndefault = [\"bar\"]\n## Foo\nfoo = []\nbar = []\n";
+        let args = Args {
+            include_undocumented: Some("Not documented".into()),
+            require_all_documented: true,
+            ..Args::default()
+        };
+        assert_eq!(process_toml(toml, &args).unwrap_err(), "Features without documentation: `bar`");
+        let args = Args { require_all_documented: false, require_default_docs: true, ..args };
+        assert_eq!(
+            process_toml(toml, &args).unwrap_err(),
+            "Default features without documentation: `bar`"
         );
+        let args = Args { require_default_docs: false, max_description_words: Some(1), ..args };
+        assert!(process_toml(toml, &args).is_ok());
     }
 
     #[test]
-    fn non_optional_dep1() {
-        test_error(
-            r#"
-[dev-dependencies]
-## Not optional
-foo = { version = "1.2", optional = false }
-"#,
-            "Dependency foo is not an optional dependency",
-        );
+    fn long_comment() {
+        let mut toml = String::from("[features]\n");
+        let mut expected = String::from("* **`foo`** —  Line 0\n");
+        for i in 0..100 {
+            writeln!(toml, "## Line {}", i).unwrap();
+            if i > 0 {
+                writeln!(expected, "  Line {}", i).unwrap();
+            }
+        }
+        toml += "foo = []\n## Bar\nbar = []\n";
+        expected += "* **`bar`** —  Bar\n";
+        assert_eq!(process_toml(&toml, &Args::default()).unwrap(), expected);
     }
 
     #[test]
-    fn non_optional_dep2() {
-        test_error(
-            r#"
-[dev-dependencies]
-## Not optional
-foo = { version = "1.2" }
-"#,
-            "Dependency foo is not an optional dependency",
+    fn legend() {
+        let toml = r#"
+[features]
+default = ["foo"]
+## Foo
+foo = []
+## Bar
+## @stability: experimental
+bar = []
+## Baz
+baz = []
+"#;
+        let args = Args { legend: true, ..Args::default() };
+        assert_eq!(
+            process_toml(toml, &args).unwrap(),
+            "Legend: *(enabled by default)* enabled by the `default` feature · \
+             *(experimental)* may change in a minor release.\n\n\
+             * **`foo`** *(enabled by default)* —  Foo\n\
+             * **`bar`** *(experimental)* —  Bar\n* **`baz`** —  Baz\n"
         );
+        let args = Args { default_icon: Some("★".into()), hide_default: true, ..args };
+        assert!(process_toml(toml, &args).unwrap().starts_with(
+            "Legend: ★ enabled by default · *(experimental)* may change in a minor release.\n\n"
+        ));
+        // No marker, no legend
+        let args = Args { legend: true, ..Args::default() };
+        let toml = "[features]\n## Foo\nfoo = []\n";
+        assert_eq!(process_toml(toml, &args).unwrap(), "* **`foo`** —  Foo\n");
     }
 
     #[test]
-    fn basic() {
+    fn validate_dep_refs() {
         let toml = r#"
-[abcd]
-[features]#xyz
-#! abc
-#
-###
-#! def
-#!
-## 123
-## 456
-feat1 = ["plop"]
-#! ghi
-no_doc = []
-##
-feat2 = ["momo"]
-#! klm
-default = ["feat1", "something_else"]
-#! end
-        "#;
-        let parsed = process_toml(toml, &Args::default()).unwrap();
+[features]
+## Foo
+foo = ["dep:a", "dep:b", "c/x", "dep:d"]
+## Bar
+bar = ["dep:missing", "dep:required"]
+[dependencies]
+a = { version = "1", optional = true }
+required = "1"
+c = { version = "1", optional = true }
+[dependencies.b]
+version = "1"
+optional = true
+[target.'cfg(unix)'.dependencies]
+d = { version = "1", optional = true }
+"#;
+        assert!(process_toml(toml, &Args::default()).is_ok());
+        let args = Args { validate_dep_refs: true, ..Args::default() };
+        // The dependencies of the workspace are not dependencies of the crate
+        let workspace_toml = r#"
+[features]
+## Foo
+foo = ["dep:e", "dep:f"]
+[workspace.dependencies]
+e = { version = "1", optional = true }
+[workspace.dependencies.f]
+version = "1"
+optional = true
+"#;
         assert_eq!(
-            parsed,
-            " abc\n def\n\n* **`feat1`** *(enabled by default)* —  123\n  456\n\n ghi\n* **`feat2`**\n\n klm\n end\n"
+            process_toml(workspace_toml, &args).unwrap_err(),
+            "Feature `foo` enables `dep:e`, but there is no such dependency\n\
+             Feature `foo` enables `dep:f`, but there is no such dependency"
         );
-        let parsed = process_toml(
-            toml,
-            &Args {
-                feature_label: Some(
-                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
-                ),
-            },
-        )
-        .unwrap();
         assert_eq!(
-            parsed,
-            " abc\n def\n\n* <span class=\"stab portability\"><code>feat1</code></span> *(enabled by default)* —  123\n  456\n\n ghi\n* <span class=\"stab portability\"><code>feat2</code></span>\n\n klm\n end\n"
+            process_toml(toml, &args).unwrap_err(),
+            "Feature `bar` enables `dep:missing`, but there is no such dependency\n\
+             Feature `bar` enables `dep:required`, but this dependency is not optional"
         );
+        let toml = toml.replace("\"dep:missing\", \"dep:required\"", "\"foo\"");
+        assert!(process_toml(&toml, &args).is_ok());
     }
 
     #[test]
-    fn dependencies() {
+    fn backtick_in_name() {
         let toml = r#"
-#! top
-[dev-dependencies] #yo
-## dep1
-dep1 = { version="1.2", optional=true}
-#! yo
-dep2 = "1.3"
-## dep3
-[target.'cfg(unix)'.build-dependencies.dep3]
-version = "42"
-optional = true
+[features]
+## Foo
+"foo`bar" = []
+## Baz
+"baz``" = []
         "#;
         let parsed = process_toml(toml, &Args::default()).unwrap();
-        assert_eq!(parsed, " top\n* **`dep1`** —  dep1\n\n yo\n* **`dep3`** —  dep3\n");
-        let parsed = process_toml(
-            toml,
-            &Args {
-                feature_label: Some(
-                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
-                ),
-            },
+        assert_eq!(parsed, "* **`` foo`bar ``** —  Foo\n* **``` baz`` ```** —  Baz\n");
+        let args =
+            Args { feature_label: Some("<code>{feature}</code>".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert_eq!(parsed, "* <code>foo`bar</code> —  Foo\n* <code>baz``</code> —  Baz\n");
+    }
+
+    #[test]
+    fn read_manifest() {
+        // Like a build script would, with an absolute path that doesn't depend on the current dir
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let cargo_toml = super::read_manifest(&dir.join("Cargo.toml")).unwrap();
+        let parsed = process_toml(&cargo_toml, &Args::default()).unwrap();
+        assert_eq!(
+            parsed,
+            "* **`self-test`** —  Internal feature used only for the tests, don't enable\n"
+        );
+        let err = super::read_manifest(&dir.join("does-not-exist/Cargo.toml")).unwrap_err();
+        assert!(err.starts_with("Can't open "), "{}", err);
+        assert!(err.contains("does-not-exist"), "{}", err);
+        // Another manifest, with its own `.orig` file
+        let root = TempDir::new("manifest");
+        std::fs::write(root.join("other.toml"), "[features]\nfoo = []\n").unwrap();
+        std::fs::write(root.join("other.toml.orig"), "[features]\n## Foo\nfoo = []\n").unwrap();
+        let cargo_toml = super::read_manifest(&root.join("other.toml")).unwrap();
+        assert_eq!(cargo_toml, "[features]\n## Foo\nfoo = []\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_manifest_orig_through_symlinks() {
+        let root = TempDir::new("symlinks");
+        let real = root.join("real");
+        std::fs::create_dir_all(&real).unwrap();
+        std::fs::write(real.join("Cargo.toml"), "[features]\nfoo = []\n").unwrap();
+        std::fs::write(real.join("Cargo.toml.orig"), "[features]\n## Foo\nfoo = []\n").unwrap();
+        // A symbolic link to the directory
+        std::os::unix::fs::symlink(&real, root.join("dir-link")).unwrap();
+        // A directory with a symbolic link to the normalized Cargo.toml only
+        let file_link = root.join("file-link");
+        std::fs::create_dir(&file_link).unwrap();
+        std::os::unix::fs::symlink(real.join("Cargo.toml"), file_link.join("Cargo.toml")).unwrap();
+
+        for dir in &[real, root.join("dir-link"), file_link] {
+            let cargo_toml = super::read_manifest(&dir.join("Cargo.toml")).unwrap();
+            assert_eq!(cargo_toml, "[features]\n## Foo\nfoo = []\n", "{:?}", dir);
+        }
+    }
+
+    #[test]
+    fn include_features() {
+        let dir = TempDir::new("include");
+        std::fs::create_dir_all(dir.join("manifest")).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[features]\ndefault = [\"foo\"]\n# @include-features: manifest/features.toml\n\
+             ## Baz\nbaz = []\n",
         )
         .unwrap();
-        assert_eq!(parsed, " top\n* <span class=\"stab portability\"><code>dep1</code></span> —  dep1\n\n yo\n* <span class=\"stab portability\"><code>dep3</code></span> —  dep3\n");
+        std::fs::write(dir.join("manifest/features.toml"), "## Foo\nfoo = []\n## Bar\nbar = []")
+            .unwrap();
+        let cargo_toml = super::read_manifest(&dir.join("Cargo.toml")).unwrap();
+        assert_eq!(
+            process_toml(&cargo_toml, &Args::default()).unwrap(),
+            "* **`foo`** *(enabled by default)* —  Foo\n* **`bar`** —  Bar\n* **`baz`** —  Baz\n"
+        );
+        std::fs::remove_file(dir.join("manifest/features.toml")).unwrap();
+        let err = super::read_manifest(&dir.join("Cargo.toml")).unwrap_err();
+        assert!(err.starts_with("Can't open the included file"), "{}", err);
+        assert!(err.contains("features.toml"), "{}", err);
+
+        // Lines in multi-line strings are not directives
+        let toml = "[package]\ndescription = \"\"\"\n# @include-features: missing.toml\n\"\"\"\n\
+                    readme = '''\n# @include-features: missing.toml\n'''\n";
+        assert_eq!(super::include_features(toml, &dir).unwrap(), toml);
+        let toml = "a = \"\"\"\\\"\"\n# @include-features: missing.toml\n\"\"\"\nb = '# \"\"\"'\n\
+                    # @include-features: missing.toml\n";
+        let err = super::include_features(toml, &dir).unwrap_err();
+        assert!(err.starts_with("Can't open the included file"), "{}", err);
     }
 
     #[test]
-    fn multi_lines() {
+    fn localized() {
         let toml = r#"
-[package.metadata.foo]
-ixyz = [
-    ["array"],
-    [
-        "of",
-        "arrays"
-    ]
-]
-[dev-dependencies]
-## dep1
-dep1 = {
-    version="1.2-}",
-    optional=true
-}
 [features]
-default = [
-    "goo",
-    "\"]",
-    "bar",
-]
-## foo
-foo = [
-   "bar"
-]
-## bar
-bar = [
-
-]
+#! [en] ### Networking
+#! [de] ### Netzwerk
+## [en] HTTP support
+##
+## [en] Uses hyper
+## [de] HTTP-Unterstützung
+##
+## [de] Verwendet hyper
+http = []
+## [en] Only in English
+english = []
+## Untagged [not a tag]
+untagged = []
+## Default text
+## [fr] Texte en français
+fallback = []
         "#;
         let parsed = process_toml(toml, &Args::default()).unwrap();
         assert_eq!(
             parsed,
-            "* **`dep1`** —  dep1\n* **`foo`** —  foo\n* **`bar`** *(enabled by default)* —  bar\n"
+            " ### Networking\n* **`http`** —  HTTP support\n \n  Uses hyper\n\
+             * **`english`** —  Only in English\n* **`untagged`** —  Untagged [not a tag]\n\
+             * **`fallback`** —  Default text\n"
         );
-        let parsed = process_toml(
-            toml,
-            &Args {
-                feature_label: Some(
-                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
-                ),
-            },
-        )
-        .unwrap();
+        let args = Args { lang: Some("de".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
         assert_eq!(
             parsed,
-            "* <span class=\"stab portability\"><code>dep1</code></span> —  dep1\n* <span class=\"stab portability\"><code>foo</code></span> —  foo\n* <span class=\"stab portability\"><code>bar</code></span> *(enabled by default)* —  bar\n"
+            " ### Netzwerk\n* **`http`** —  HTTP-Unterstützung\n \n  Verwendet hyper\n\
+             * **`english`** —  Only in English\n* **`untagged`** —  Untagged [not a tag]\n\
+             * **`fallback`** —  Default text\n"
         );
+        let args = Args { lang: Some("fr".into()), ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
+        assert!(parsed.ends_with("* **`fallback`** —  Texte en français\n"), "{}", parsed);
     }
 
     #[test]
-    fn dots_in_feature() {
+    fn mermaid() {
         let toml = r#"
 [features]
-## This is a test
-"teßt." = []
-default = ["teßt."]
+default = ["std"]
+## Std
+std = ["alloc", "serde?/std"]
+alloc = []
+## Serde support
+serde = ["dep:serde", "alloc"]
 [dependencies]
-## A dep
-"dep" = { version = "123", optional = true }
+serde = { version = "1", optional = true }
         "#;
-        let parsed = process_toml(toml, &Args::default()).unwrap();
-        assert_eq!(
-            parsed,
-            "* **`teßt.`** *(enabled by default)* —  This is a test\n* **`dep`** —  A dep\n"
-        );
-        let parsed = process_toml(
-            toml,
-            &Args {
-                feature_label: Some(
-                    "<span class=\"stab portability\"><code>{feature}</code></span>".into(),
-                ),
-            },
-        )
-        .unwrap();
+        let args = Args { format: Format::Mermaid, ..Default::default() };
+        let parsed = process_toml(toml, &args).unwrap();
         assert_eq!(
             parsed,
-            "* <span class=\"stab portability\"><code>teßt.</code></span> *(enabled by default)* —  This is a test\n* <span class=\"stab portability\"><code>dep</code></span> —  A dep\n"
+            "```mermaid\ngraph TD\n    f0[\"default\"]\n    f1[\"std\"]\n    f2[\"alloc\"]\n    f3[\"serde\"]\n\
+             \x20   f0 --> f1\n    f1 --> f2\n    f4([\"serde?/std\"]):::dep\n    f1 --> f4\n\
+             \x20   f5([\"serde\"]):::dep\n    f3 --> f5\n    f3 --> f2\n\
+             \x20   classDef dep stroke-dasharray: 5 5\n```\n"
         );
     }
 
     #[test]
-    fn recursive_default() {
+    fn empty_doc_line() {
         let toml = r#"
 [features]
-default=["qqq"]
-
-## Qqq
-qqq=["www"]
-
-## Www
-www=[]
+## para1
+##
+## para2
+foo = []
+## bar
+bar = []
         "#;
         let parsed = process_toml(toml, &Args::default()).unwrap();
-        assert_eq!(parsed, "* **`qqq`** *(enabled by default)* —  Qqq\n* **`www`** *(enabled by default)* —  Www\n");
+        // The empty line is a blank line in markdown, and the second paragraph is indented
+        // so that it is still part of the list item
+        assert_eq!(parsed, "* **`foo`** —  para1\n \n  para2\n* **`bar`** —  bar\n");
+    }
+
+    #[test]
+    fn generate_items() {
+        let toml = r#"
+[features]
+## The foo feature
+## on two lines
+foo-bar = []
+[dependencies]
+## A "dep"
+my_dep = { version = "1", optional = true }
+        "#;
+        let parsed = parse_toml(toml, &Args::default()).unwrap();
+        let code = super::generate_items("features", &parsed.features).unwrap();
+        assert!(code.contains("#[doc(hidden)]\npub mod features {\n"), "{}", code);
+        assert!(
+            code.contains(
+                "    #[doc = \"The foo feature\\n  on two lines\"]\n    pub struct FooBar;\n\
+                 \x20   impl FooBar {\n\
+                 \x20       /// The name of the feature in Cargo.toml\n\
+                 \x20       pub const NAME: &'static str = \"foo-bar\";\n    }\n"
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("    #[doc = \"A \\\"dep\\\"\"]\n    pub struct MyDep;\n"),
+            "{}",
+            code
+        );
     }
 }
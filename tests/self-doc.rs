@@ -13,6 +13,7 @@ fn ensure_it_compiles() {
     document_features::document_features!(
         feature_label = r##"<span class="stab portability"><code>{feature}</code></span>"##
     );
+    document_features::document_features!(format = "badges", badge_color = "red");
 }
 
 #[test]
@@ -35,3 +36,66 @@ fn self_doc_with_custom_label() {
     );
     assert_eq!(actual2, expected);
 }
+
+#[test]
+fn self_doc_badges() {
+    let actual = document_features::document_features!(format = "badges");
+    let expected = "![self-test](https://img.shields.io/badge/feature-self--test-blue)\n";
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn self_doc_manifest_path() {
+    let actual = document_features::document_features!(manifest_path = "./Cargo.toml");
+    assert_eq!(actual, document_features::document_features!());
+}
+
+document_features::document_features_enum!(Features);
+
+#[test]
+fn self_doc_enum() {
+    assert_eq!(Features::ALL, &[Features::SelfTest]);
+    let name = match Features::SelfTest {
+        Features::SelfTest => "self-test",
+    };
+    assert_eq!(Features::SelfTest.as_str(), name);
+    assert_eq!(Features::SelfTest.to_string(), name);
+}
+
+#[cfg(feature = "self-test")]
+#[test]
+fn special_sequences_in_description() {
+    let actual = document_features::self_test_helper!(
+        r#"
+[features]
+## Closes */ then opens /* with a \ backslash, a "quote" and \n
+foo = []
+"#
+    );
+    let expected =
+        "* **`foo`** —  Closes */ then opens /* with a \\ backslash, a \"quote\" and \\n\n";
+    assert_eq!(actual, expected);
+}
+
+#[cfg(feature = "self-test")]
+#[test]
+fn macro_invocation_in_description() {
+    let actual = document_features::self_test_helper!(
+        r#"
+[features]
+## Use `#![doc = document_features::document_features!(feature_label = "{feature}")]`
+foo = []
+"#
+    );
+    let expected = "* **`foo`** —  Use `#![doc = document_features::document_features!(feature_label = \"{feature}\")]`\n";
+    assert_eq!(actual, expected);
+}
+
+document_features::document_features_items!();
+document_features::document_features_items!(other_features);
+
+#[test]
+fn self_doc_items() {
+    assert_eq!(features::SelfTest::NAME, "self-test");
+    let _: other_features::SelfTest = other_features::SelfTest;
+}